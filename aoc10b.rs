@@ -1,79 +1,719 @@
 use std::env;
+use std::cmp;
 use std::fs::File;
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, Read, Write};
 use std::path::Path;
-use std::collections::BTreeMap;
+
+// A pair of delimiters, which may be words such as begin/end
+struct Pair {
+    open: String,
+    close: String,
+    // None when a config or --pair gave no score
+    score: Option<usize>
+}
+
+impl Pair {
+    fn new(open: &str, close: &str, score: usize) -> Pair {
+        Pair {
+            open: String::from(open),
+            close: String::from(close),
+            score: Some(score)
+        }
+    }
+
+    // Parses "open close [penalty [score]]", the format used in config files
+    fn parse(spec: &str) -> Result<Pair, String> {
+        let fields: Vec<&str> = spec.split_whitespace().collect();
+        if fields.len() < 2 || fields.len() > 4 {
+            return Err(format!("expected \"open close [penalty [score]]\" but found {:?}", spec));
+        }
+        let mut pair = Pair::new(fields[0], fields[1], 0);
+        pair.score = match fields.get(3) {
+            Some(value) => Some(value.parse().map_err(|e| format!("invalid score {:?}: {}", value, e))?),
+            None => None
+        };
+        Ok(pair)
+    }
+
+    // Reads one pair or syntax rule per line, skipping blank lines and # comments
+    fn load(path: &str, syntax: &mut Syntax) -> Result<Vec<Pair>, String> {
+        let lines = read_lines(path).map_err(|e| format!("cannot read {:?}: {}", path, e))?;
+        let mut pairs: Vec<Pair> = Vec::new();
+        for line in lines {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim() != "" && !line.trim_start().starts_with('#') && !syntax.add(&line)? {
+                pairs.push(Pair::parse(&line)?);
+            }
+        }
+        Ok(pairs)
+    }
+}
+
+// How strings and comments are written, so that delimiters inside them are ignored
+struct Syntax {
+    // Quote and optional escape
+    quotes: Vec<(String, Option<String>)>,
+    line_comments: Vec<String>,
+    block_comments: Vec<(String, String)>
+}
+
+impl Syntax {
+    fn new() -> Syntax {
+        Syntax {
+            quotes: Vec::new(),
+            line_comments: Vec::new(),
+            block_comments: Vec::new()
+        }
+    }
+
+    // Adds "string QUOTE [ESCAPE]" or "comment START [END]", returning false
+    // for anything else
+    fn add(&mut self, spec: &str) -> Result<bool, String> {
+        let fields: Vec<&str> = spec.split_whitespace().collect();
+        match (fields.first().cloned(), fields.len()) {
+            (Some("string"), 2) => self.quotes.push((String::from(fields[1]), None)),
+            (Some("string"), 3) => self.quotes.push((String::from(fields[1]), Some(String::from(fields[2])))),
+            (Some("comment"), 2) => self.line_comments.push(String::from(fields[1])),
+            (Some("comment"), 3) => self.block_comments.push((String::from(fields[1]), String::from(fields[2]))),
+            (Some("string"), _) | (Some("comment"), _) => return Err(format!("expected \"string QUOTE [ESCAPE]\" or \"comment START [END]\" but found {:?}", spec)),
+            _ => return Ok(false)
+        }
+        Ok(true)
+    }
+
+    fn lexemes(&self) -> Vec<usize> {
+        let mut result: Vec<usize> = Vec::new();
+        for (quote, escape) in &self.quotes {
+            result.push(quote.len());
+            // An escape swallows the byte after it too
+            result.push(escape.as_ref().map_or(0, |e| e.len() + 1));
+        }
+        result.extend(self.line_comments.iter().map(|c| c.len()));
+        for (start, end) in &self.block_comments {
+            result.push(start.len());
+            result.push(end.len());
+        }
+        result
+    }
+}
+
+// Where the lexer is: in code, or inside a string or comment of a given kind
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Code,
+    Quoted(usize),
+    LineComment,
+    BlockComment(usize)
+}
+
+#[derive(Clone, Copy)]
+struct Token {
+    pair: usize,
+    open: bool,
+    start: usize
+}
+
+// Columns are 1-based character positions within the line
+enum LineStatus {
+    Valid,
+    Corrupted { found: String, expected: Option<String>, column: usize, opened_at: Option<usize> },
+    Incomplete { missing_closers: Vec<String> }
+}
+
+impl LineStatus {
+    // A single line description, for when the line itself is too long to echo
+    fn summary(&self) -> Option<String> {
+        match self {
+            LineStatus::Valid => None,
+            LineStatus::Corrupted { found, expected: Some(e), column, .. } => Some(format!("column {}: expected `{}`, found `{}`", column, e, found)),
+            LineStatus::Corrupted { found, column, .. } => Some(format!("column {}: unexpected `{}`", column, found)),
+            LineStatus::Incomplete { missing_closers } => Some(format!("incomplete, missing {} closers", missing_closers.len()))
+        }
+    }
+}
 
 struct Chunks {
-    close: BTreeMap<char, char>,
-    score: BTreeMap<char, usize>
+    pairs: Vec<Pair>,
+    syntax: Syntax
 }
 
 impl Chunks {
     fn new() -> Chunks {
-        let mut close: BTreeMap<char, char> = BTreeMap::new();
-        let mut score: BTreeMap<char, usize> = BTreeMap::new();
-        close.insert('(', ')');
-        close.insert('[', ']');
-        close.insert('{', '}');
-        close.insert('<', '>');
-        score.insert('(', 1);
-        score.insert('[', 2);
-        score.insert('{', 3);
-        score.insert('<', 4);
+        Chunks::with_pairs(vec![
+            Pair::new("(", ")", 1),
+            Pair::new("[", "]", 2),
+            Pair::new("{", "}", 3),
+            Pair::new("<", ">", 4)
+        ])
+    }
 
+    fn with_pairs(pairs: Vec<Pair>) -> Chunks {
         Chunks {
-            close: close,
-            score: score
+            pairs,
+            syntax: Syntax::new()
+        }
+    }
+
+    fn word(b: u8) -> bool {
+        b.is_ascii_alphanumeric() || b == b'_'
+    }
+
+    // Does the delimiter appear at pos, as a whole word if it is made of letters?
+    fn matches(line: &[u8], pos: usize, delimiter: &str) -> bool {
+        let d = delimiter.as_bytes();
+        if d.is_empty() || !line[pos..].starts_with(d) {
+            return false;
+        }
+        if Chunks::word(d[0]) && pos > 0 && Chunks::word(line[pos - 1]) {
+            return false;
+        }
+        let end = pos + d.len();
+        if Chunks::word(d[d.len() - 1]) && end < line.len() && Chunks::word(line[end]) {
+            return false;
         }
+        true
     }
 
-    fn expected_match(&self, ch: char) -> Option<char> {
-        if let Some(result) = self.close.get(&ch) {
-            return Some(*result);
+    // The delimiter starting at pos, preferring the longest when several match
+    fn delimiter_at(&self, buf: &[u8], pos: usize) -> Option<(usize, Token)> {
+        let mut best: Option<(usize, Token)> = None;
+        for (i, pair) in self.pairs.iter().enumerate() {
+            for (delimiter, open) in [(&pair.open, true), (&pair.close, false)] {
+                if Chunks::matches(buf, pos, delimiter) && best.is_none_or(|(len, _)| delimiter.len() > len) {
+                    best = Some((delimiter.len(), Token { pair: i, open, start: pos }));
+                }
+            }
         }
-        None
+        best
     }
 
-    fn is_open(&self, ch: char) -> bool {
-        return self.close.contains_key(&ch);
+    fn longest(&self) -> usize {
+        let delimiters = self.pairs.iter().map(|p| cmp::max(p.open.len(), p.close.len()));
+        delimiters.chain(self.syntax.lexemes()).max().unwrap_or(1)
     }
 
-    fn score(&self, ch: char, score: usize) -> usize {
-        if let Some(s) = self.score.get(&ch) {
-            return score * 5 + *s;
+    // Moves past the next lexeme at pos, returning where it ends and the
+    // delimiter found, if any
+    fn lex(&self, mode: &mut Mode, buf: &[u8], pos: usize) -> (usize, Option<Token>) {
+        let rest = &buf[pos..];
+        match *mode {
+            Mode::Code => {
+                if let Some(c) = self.syntax.line_comments.iter().find(|c| rest.starts_with(c.as_bytes())) {
+                    *mode = Mode::LineComment;
+                    return (pos + c.len(), None);
+                }
+                for (i, (start, _)) in self.syntax.block_comments.iter().enumerate() {
+                    if rest.starts_with(start.as_bytes()) {
+                        *mode = Mode::BlockComment(i);
+                        return (pos + start.len(), None);
+                    }
+                }
+                for (i, (quote, _)) in self.syntax.quotes.iter().enumerate() {
+                    if rest.starts_with(quote.as_bytes()) {
+                        *mode = Mode::Quoted(i);
+                        return (pos + quote.len(), None);
+                    }
+                }
+                match self.delimiter_at(buf, pos) {
+                    Some((len, token)) => (pos + len, Some(token)),
+                    None => (pos + 1, None)
+                }
+            },
+            Mode::Quoted(i) => {
+                let (quote, escape) = &self.syntax.quotes[i];
+                if let Some(e) = escape {
+                    if rest.starts_with(e.as_bytes()) {
+                        return (cmp::min(pos + e.len() + 1, buf.len()), None);
+                    }
+                }
+                if rest.starts_with(quote.as_bytes()) {
+                    *mode = Mode::Code;
+                    return (pos + quote.len(), None);
+                }
+                (pos + 1, None)
+            },
+            Mode::LineComment => {
+                if rest[0] == b'\n' {
+                    *mode = Mode::Code;
+                }
+                (pos + 1, None)
+            },
+            Mode::BlockComment(i) => {
+                let end = &self.syntax.block_comments[i].1;
+                if rest.starts_with(end.as_bytes()) {
+                    *mode = Mode::Code;
+                    return (pos + end.len(), None);
+                }
+                (pos + 1, None)
+            }
+        }
+    }
+
+    // Splits a line into delimiters, skipping strings, comments and any other text
+    fn tokens(&self, str: &str) -> Vec<Token> {
+        let line = str.as_bytes();
+        let mut result: Vec<Token> = Vec::new();
+        let mut mode = Mode::Code;
+        let mut pos = 0;
+        while pos < line.len() {
+            let (next, token) = self.lex(&mut mode, line, pos);
+            result.extend(token);
+            pos = next;
+        }
+        result
+    }
+
+    fn column(str: &str, start: usize) -> usize {
+        str[..start].chars().count() + 1
+    }
+
+    fn classify(&self, str: &str) -> LineStatus {
+        let mut validator = Validator::new(self);
+        validator.feed(str.as_bytes());
+        validator.finish();
+        validator.status(|start| Chunks::column(str, start))
+    }
+
+    // None once the score no longer fits, which long streamed lines can reach
+    fn score(&self, close: &str, score: usize) -> Option<usize> {
+        if let Some(pair) = self.pairs.iter().find(|p| p.close == close) {
+            return score.checked_mul(5)?.checked_add(pair.score.unwrap_or(0));
         }
-        0
+        Some(0)
     }
 
-    fn calculate(&self, str: &str) -> usize {
-        let mut v: Vec<char> = str.chars().collect();
-        let mut idx: usize = 0;
-        let mut score: usize = 0;
-        for i in 1..v.len() {
-            if self.is_open(v[i]) {
-                idx = i;
-            } else if Some(v[i]) == self.expected_match(v[idx]) {
-                // Replace matching braces with spaces
-                v[i] = ' ';
-                v[idx] = ' ';
-                // Move idx back to non-space character or back to start
-                while idx > 0 && v[idx] == ' ' {
-                    idx -= 1;
+    fn calculate(&self, status: &LineStatus) -> Option<usize> {
+        match status {
+            LineStatus::Incomplete { missing_closers } => missing_closers.iter().try_fold(0, |score, c| self.score(c, score)),
+            _ => Some(0)
+        }
+    }
+
+    // Makes a line balanced: incomplete lines have their completion appended and
+    // corrupted lines get the fewest insertions, deletions and substitutions.
+    // Finding the fewest takes cubic time, so corrupted lines with more than
+    // MAX_REPAIR_TOKENS delimiters are repaired greedily instead, and the flag
+    // returned with the edits is false
+    fn repair(&self, str: &str, status: &LineStatus) -> (Vec<Edit>, bool) {
+        match status {
+            LineStatus::Valid => (Vec::new(), true),
+            LineStatus::Incomplete { missing_closers } => {
+                (vec![Edit::Insert { at: str.len(), text: Diagnostic::join(missing_closers) }], true)
+            },
+            LineStatus::Corrupted { .. } => {
+                let tokens = self.tokens(str);
+                if tokens.len() > MAX_REPAIR_TOKENS {
+                    return (self.greedy(str, &tokens), false);
+                }
+                let repair = Repair::new(&tokens);
+                let mut edits: Vec<Edit> = Vec::new();
+                repair.edits(self, str, 0, tokens.len(), &mut edits);
+                // Stable, so insertions at the same place stay nested correctly
+                edits.sort_by_key(|e| e.at());
+                (edits, true)
+            }
+        }
+    }
+
+    // Matches delimiters against a stack, replacing each closer that doesn't match
+    // the innermost open chunk with its closer, deleting closers with nothing open,
+    // and closing whatever is left open at the end of the line
+    fn greedy(&self, str: &str, tokens: &[Token]) -> Vec<Edit> {
+        let mut edits: Vec<Edit> = Vec::new();
+        let mut stack: Vec<usize> = Vec::new();
+        for token in tokens {
+            if token.open {
+                stack.push(token.pair);
+                continue;
+            }
+            let from = self.pairs[token.pair].close.clone();
+            match stack.pop() {
+                Some(open) if open == token.pair => {},
+                Some(open) => edits.push(Edit::Substitute { at: token.start, from, to: self.pairs[open].close.clone() }),
+                None => edits.push(Edit::Delete { at: token.start, text: from })
+            }
+        }
+        while let Some(open) = stack.pop() {
+            edits.push(Edit::Insert { at: str.len(), text: self.pairs[open].close.clone() });
+        }
+        edits
+    }
+}
+
+enum Edit {
+    Insert { at: usize, text: String },
+    Delete { at: usize, text: String },
+    Substitute { at: usize, from: String, to: String }
+}
+
+impl Edit {
+    fn at(&self) -> usize {
+        match self {
+            Edit::Insert { at, .. } | Edit::Delete { at, .. } | Edit::Substitute { at, .. } => *at
+        }
+    }
+
+    fn describe(&self, str: &str) -> String {
+        let column = Chunks::column(str, self.at());
+        match self {
+            Edit::Insert { text, .. } => format!("inserted `{}` at column {}", text, column),
+            Edit::Delete { text, .. } => format!("deleted `{}` at column {}", text, column),
+            Edit::Substitute { from, to, .. } => format!("replaced `{}` with `{}` at column {}", from, to, column)
+        }
+    }
+
+    // Applies edits sorted by position, keeping word delimiters apart from the text around them
+    fn apply(str: &str, edits: &[Edit]) -> String {
+        let mut result = String::new();
+        let mut pos = 0;
+        for edit in edits {
+            result.push_str(&str[pos..edit.at()]);
+            pos = edit.at();
+            match edit {
+                Edit::Insert { text, .. } => {
+                    let before = result.as_bytes().last().is_some_and(|b| Chunks::word(*b));
+                    let after = str.as_bytes().get(pos).is_some_and(|b| Chunks::word(*b));
+                    if before && Chunks::word(text.as_bytes()[0]) {
+                        result.push(' ');
+                    }
+                    result.push_str(text);
+                    if after && Chunks::word(text.as_bytes()[text.len() - 1]) {
+                        result.push(' ');
+                    }
+                },
+                Edit::Delete { text, .. } => pos += text.len(),
+                Edit::Substitute { from, to, .. } => {
+                    result.push_str(to);
+                    pos += from.len();
+                }
+            }
+        }
+        result.push_str(&str[pos..]);
+        result
+    }
+}
+
+// Longest run of delimiters given a minimal repair, which needs an (n+1)^2 table
+// and cubic time: 500 delimiters is about 2 MB and well under a second
+const MAX_REPAIR_TOKENS: usize = 500;
+
+// Interval table of the cheapest way to balance each run of tokens
+struct Repair<'a> {
+    tokens: &'a Vec<Token>,
+    cost: Vec<Vec<usize>>
+}
+
+impl<'a> Repair<'a> {
+    fn new(tokens: &'a Vec<Token>) -> Repair<'a> {
+        let n = tokens.len();
+        let mut cost: Vec<Vec<usize>> = vec![vec![0; n + 1]; n + 1];
+        for len in 1..=n {
+            for i in 0..=n - len {
+                let j = i + len;
+                // Fix token i on its own, then try pairing it with each later token
+                let mut best = 1 + cost[i + 1][j];
+                for k in i + 1..j {
+                    let c = Repair::pair_cost(&tokens[i], &tokens[k]) + cost[i + 1][k] + cost[k + 1][j];
+                    best = cmp::min(best, c);
                 }
+                cost[i][j] = best;
+            }
+        }
+        Repair {
+            tokens,
+            cost
+        }
+    }
+
+    // Substitutions needed to make a and b an opener and its closer
+    fn pair_cost(a: &Token, b: &Token) -> usize {
+        match (a.open, b.open) {
+            (true, false) if a.pair == b.pair => 0,
+            (true, _) | (false, false) => 1,
+            (false, true) => 2
+        }
+    }
+
+    fn edits(&self, chunks: &Chunks, str: &str, i: usize, j: usize, edits: &mut Vec<Edit>) {
+        if i >= j {
+            return;
+        }
+        let a = &self.tokens[i];
+        if self.cost[i][j] == 1 + self.cost[i + 1][j] {
+            self.edits(chunks, str, i + 1, j, edits);
+            let pair = &chunks.pairs[a.pair];
+            if a.open {
+                // Close an unmatched opener after everything it encloses
+                let at = if j < self.tokens.len() { self.tokens[j].start } else { str.len() };
+                edits.push(Edit::Insert { at, text: pair.close.clone() });
             } else {
-                return 0;
+                edits.push(Edit::Delete { at: a.start, text: pair.close.clone() });
+            }
+            return;
+        }
+        for k in i + 1..j {
+            let b = &self.tokens[k];
+            if self.cost[i][j] == Repair::pair_cost(a, b) + self.cost[i + 1][k] + self.cost[k + 1][j] {
+                let text = |t: &Token| if t.open { chunks.pairs[t.pair].open.clone() } else { chunks.pairs[t.pair].close.clone() };
+                // A closer turned into an opener takes the type of the token it pairs with
+                let kind = if a.open { a.pair } else if !b.open { b.pair } else { a.pair };
+                if !a.open {
+                    edits.push(Edit::Substitute { at: a.start, from: text(a), to: chunks.pairs[kind].open.clone() });
+                }
+                self.edits(chunks, str, i + 1, k, edits);
+                if b.open || b.pair != kind {
+                    edits.push(Edit::Substitute { at: b.start, from: text(b), to: chunks.pairs[kind].close.clone() });
+                }
+                self.edits(chunks, str, k + 1, j, edits);
+                return;
             }
         }
-        v.reverse();
-        let _str: String = v.iter()
-                           .filter(|c| *c != &' ')
-                           .inspect(|&c| score = self.score(*c, score))
-                           .collect();
-        score
     }
 }
 
+// Matches delimiters against a stack as bytes arrive, so lines of any length
+// can be checked without holding them in memory
+struct Validator<'a> {
+    chunks: &'a Chunks,
+    longest: usize,
+    mode: Mode,
+    stack: Vec<Token>,
+    // Offsets of the line breaks seen, used to locate errors across a whole file
+    newlines: Vec<usize>,
+    // Bytes not yet tokenised, preceded by the last byte consumed for word boundaries
+    pending: Vec<u8>,
+    // Offset in the line of pending[0]
+    base: usize,
+    // Index in pending to resume scanning from
+    scan: usize,
+    // The first closer that did not match, and the opener it met
+    corrupted: Option<(Token, Option<Token>)>
+}
+
+impl<'a> Validator<'a> {
+    fn new(chunks: &'a Chunks) -> Validator<'a> {
+        Validator {
+            chunks,
+            longest: chunks.longest(),
+            mode: Mode::Code,
+            stack: Vec::new(),
+            newlines: Vec::new(),
+            pending: Vec::new(),
+            base: 0,
+            scan: 0,
+            corrupted: None
+        }
+    }
+
+    fn reset(&mut self) {
+        self.mode = Mode::Code;
+        self.stack.clear();
+        self.newlines.clear();
+        self.pending.clear();
+        self.base = 0;
+        self.scan = 0;
+        self.corrupted = None;
+    }
+
+    fn feed(&mut self, bytes: &[u8]) {
+        // Nothing after the first error changes the outcome
+        if self.corrupted.is_none() {
+            self.pending.extend_from_slice(bytes);
+            self.run(false);
+        }
+    }
+
+    // Called at the end of a line to settle any delimiter still in doubt
+    fn finish(&mut self) {
+        if self.corrupted.is_none() {
+            self.run(true);
+        }
+    }
+
+    fn run(&mut self, end: bool) {
+        let mut pos = self.scan;
+        // A delimiter can only be decided once the byte after the longest one has arrived
+        while pos < self.pending.len() && (end || self.pending.len() - pos > self.longest) {
+            let (next, token) = self.chunks.lex(&mut self.mode, &self.pending, pos);
+            for i in pos..next {
+                if self.pending[i] == b'\n' {
+                    self.newlines.push(self.base + i);
+                }
+            }
+            pos = next;
+            if let Some(mut token) = token {
+                token.start += self.base;
+                if !self.push(token) {
+                    self.pending.clear();
+                    self.scan = 0;
+                    return;
+                }
+            }
+        }
+        let keep = if pos > 0 { pos - 1 } else { 0 };
+        self.pending.drain(..keep);
+        self.base += keep;
+        self.scan = pos - keep;
+    }
+
+    fn push(&mut self, token: Token) -> bool {
+        if token.open {
+            self.stack.push(token);
+            return true;
+        }
+        match self.stack.last() {
+            Some(opener) if opener.pair == token.pair => {
+                self.stack.pop();
+                true
+            },
+            top => {
+                self.corrupted = Some((token, top.cloned()));
+                false
+            }
+        }
+    }
+
+    // Line and column of a byte offset, counting from the last reset
+    fn locate(&self, offset: usize) -> (usize, usize) {
+        let line = self.newlines.iter().take_while(|n| **n < offset).count();
+        let start = if line > 0 { self.newlines[line - 1] + 1 } else { 0 };
+        (line + 1, offset - start + 1)
+    }
+
+    // Describes the outcome of validating a whole file rather than a single line
+    fn describe(&self) -> Option<String> {
+        let pairs = &self.chunks.pairs;
+        if let Some((t, opener)) = self.corrupted {
+            let (line, column) = self.locate(t.start);
+            return Some(match opener {
+                Some(o) => {
+                    let (l, c) = self.locate(o.start);
+                    format!("line {}, column {}: expected `{}`, found `{}` (opened at line {}, column {})", line, column, pairs[o.pair].close, pairs[t.pair].close, l, c)
+                },
+                None => format!("line {}, column {}: unexpected `{}`", line, column, pairs[t.pair].close)
+            });
+        }
+        match self.mode {
+            Mode::Quoted(_) => return Some(String::from("end of input inside a string")),
+            Mode::BlockComment(_) => return Some(String::from("end of input inside a comment")),
+            _ => {}
+        }
+        self.stack.last().map(|o| {
+            let (line, column) = self.locate(o.start);
+            format!("end of input: {} unclosed, innermost `{}` opened at line {}, column {}", self.stack.len(), pairs[o.pair].open, line, column)
+        })
+    }
+
+    // Converts byte offsets within the line to columns using the function given
+    fn status(&self, column: impl Fn(usize) -> usize) -> LineStatus {
+        let pairs = &self.chunks.pairs;
+        match self.corrupted {
+            Some((t, opener)) => LineStatus::Corrupted {
+                found: pairs[t.pair].close.clone(),
+                expected: opener.map(|o| pairs[o.pair].close.clone()),
+                column: column(t.start),
+                opened_at: opener.map(|o| column(o.start))
+            },
+            None if self.stack.is_empty() => LineStatus::Valid,
+            None => LineStatus::Incomplete {
+                missing_closers: self.stack.iter().rev().map(|t| pairs[t.pair].close.clone()).collect()
+            }
+        }
+    }
+}
+
+// Validates a file in fixed size reads, reporting each line as it ends, or
+// the whole file as one unit when line breaks are not significant
+fn stream(chunks: &Chunks, filename: &str, whole: bool, mut report: impl FnMut(usize, &Validator)) -> io::Result<()> {
+    let mut file = File::open(filename)?;
+    let mut buffer = vec![0u8; 1 << 16];
+    let mut validator = Validator::new(chunks);
+    let mut line: usize = 1;
+    let mut started: bool = false;
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        let mut rest = &buffer[..n];
+        while let Some(i) = rest.iter().position(|b| *b == b'\n' && !whole) {
+            validator.feed(&rest[..i]);
+            validator.finish();
+            report(line, &validator);
+            validator.reset();
+            line += 1;
+            started = false;
+            rest = &rest[i + 1..];
+        }
+        if !rest.is_empty() {
+            validator.feed(rest);
+            started = true;
+        }
+    }
+    if started {
+        validator.finish();
+        report(line, &validator);
+    }
+    Ok(())
+}
+
+// Renders a line status in the style of a compiler error
+struct Diagnostic<'a> {
+    file: &'a str,
+    line: usize,
+    text: &'a str
+}
+
+impl<'a> Diagnostic<'a> {
+    // Closers are run together when they are single characters, as in the puzzle
+    fn join(closers: &[String]) -> String {
+        if closers.iter().all(|c| c.chars().count() == 1) {
+            closers.concat()
+        } else {
+            closers.join(" ")
+        }
+    }
+
+    fn render(&self, chunks: &Chunks, status: &LineStatus) -> Option<String> {
+        let number = self.line.to_string();
+        let gutter = " ".repeat(number.len());
+        let mut out: Vec<String> = Vec::new();
+        match status {
+            LineStatus::Valid => return None,
+            LineStatus::Corrupted { found, expected, column, opened_at } => {
+                match expected {
+                    Some(e) => out.push(format!("error: mismatched closing delimiter: expected `{}`, found `{}`", e, found)),
+                    None => out.push(format!("error: unexpected closing delimiter: `{}`", found))
+                }
+                out.push(format!("{}--> {}:{}:{}", gutter, self.file, self.line, column));
+                out.push(format!("{} |", gutter));
+                out.push(format!("{} | {}", number, self.text));
+                let caret = "^".repeat(found.chars().count());
+                match (opened_at, expected) {
+                    (Some(o), Some(e)) => {
+                        let opener = chunks.pairs.iter().find(|p| &p.close == e).map_or("", |p| p.open.as_str());
+                        let width = cmp::min(cmp::max(opener.chars().count(), 1), column - o);
+                        let marker = format!("{}{}", " ".repeat(o - 1), "-".repeat(width));
+                        out.push(format!("{} | {}{}{} expected `{}`", gutter, marker, " ".repeat(column - 1 - marker.len()), caret, e));
+                        out.push(format!("{} | {}|", gutter, " ".repeat(o - 1)));
+                        out.push(format!("{} | {}unclosed `{}` opened here", gutter, " ".repeat(o - 1), opener));
+                    },
+                    _ => out.push(format!("{} | {}{} no matching opener", gutter, " ".repeat(column - 1), caret))
+                }
+            },
+            LineStatus::Incomplete { missing_closers } => {
+                let end = self.text.chars().count() + 1;
+                let missing = Diagnostic::join(missing_closers);
+                out.push(format!("warning: incomplete line, missing `{}`", missing));
+                out.push(format!("{}--> {}:{}:{}", gutter, self.file, self.line, end));
+                out.push(format!("{} |", gutter));
+                out.push(format!("{} | {}", number, self.text));
+                out.push(format!("{} | {}^ expected `{}`", gutter, " ".repeat(end - 1), missing));
+            }
+        }
+        Some(out.join("\n"))
+    }
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -83,23 +723,155 @@ fn main() {
         return;
     }
 
-    println!("Reading {:?}", &args[1]);
-    if let Ok(lines) = read_lines(&args[1]) {
-        let chunks = Chunks::new();
+    let mut filename: Option<&String> = None;
+    let mut pairs: Vec<Pair> = Vec::new();
+    let mut diagnostics: bool = false;
+    let mut streaming: bool = false;
+    let mut whole: bool = false;
+    let mut syntax = Syntax::new();
+    let mut fix: bool = false;
+    let mut i = 1;
+    while i < args.len() {
+        let loaded = match args[i].as_str() {
+            "--config" => {
+                i += 1;
+                args.get(i).ok_or(String::from("Missing value for --config")).and_then(|path| Pair::load(path, &mut syntax))
+            },
+            "--pair" => {
+                i += 1;
+                args.get(i).ok_or(String::from("Missing value for --pair")).and_then(|spec| Pair::parse(spec)).map(|p| vec![p])
+            },
+            "--diagnostics" => {
+                diagnostics = true;
+                Ok(Vec::new())
+            },
+            "--stream" => {
+                streaming = true;
+                Ok(Vec::new())
+            },
+            "--whole" => {
+                streaming = true;
+                whole = true;
+                Ok(Vec::new())
+            },
+            "--string" | "--comment" => {
+                let rule = format!("{} {}", &args[i][2..], args.get(i + 1).map_or("", |v| v.as_str()));
+                i += 1;
+                syntax.add(&rule).map(|_| Vec::new())
+            },
+            "--fix" => {
+                fix = true;
+                Ok(Vec::new())
+            },
+            _ => {
+                filename = Some(&args[i]);
+                Ok(Vec::new())
+            }
+        };
+        match loaded {
+            Ok(p) => pairs.extend(p),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        }
+        i += 1;
+    }
+    let filename = match filename {
+        Some(f) => f,
+        None => {
+            println!("No input file specified");
+            return;
+        }
+    };
+
+    if streaming && fix {
+        println!("--fix needs whole lines and cannot be used with --stream");
+        return;
+    }
+
+    for pair in pairs.iter().filter(|p| p.score.is_none()) {
+        println!("Warning: pair {} {} has no score, so a missing {} adds nothing to completion scores", pair.open, pair.close, pair.close);
+    }
+
+    println!("Reading {:?}", filename);
+    let mut chunks = if pairs.is_empty() { Chunks::new() } else { Chunks::with_pairs(pairs) };
+    chunks.syntax = syntax;
+    if streaming {
         let mut scores: Vec<usize> = Vec::new();
-        for line in lines {
+        let result = stream(&chunks, filename, whole, |n, validator| {
+            let status = validator.status(|start| start + 1);
+            if diagnostics {
+                if whole {
+                    if let Some(description) = validator.describe() {
+                        println!("{}: {}", filename, description);
+                    }
+                } else if let Some(summary) = status.summary() {
+                    println!("{}:{}: {}", filename, n, summary);
+                }
+            }
+            if let LineStatus::Incomplete { .. } = status {
+                match chunks.calculate(&status) {
+                    Some(score) => scores.push(score),
+                    None => println!("{}:{}: completion score is too large, line skipped", filename, n)
+                }
+            }
+        });
+        if let Err(e) = result {
+            println!("Failed to read {:?}: {}", filename, e);
+            return;
+        }
+        scores.sort();
+        if scores.is_empty() {
+            println!("No incomplete lines");
+        } else {
+            println!("Result: {}", scores[(scores.len() - 1) / 2]);
+        }
+    } else if let Ok(lines) = read_lines(filename) {
+        let mut scores: Vec<usize> = Vec::new();
+        let mut fixed: Vec<String> = Vec::new();
+        for (n, line) in lines.enumerate() {
             if let Ok(strval) = line {
-                let score: usize = chunks.calculate(&strval);
-                if score > 0 {
-//                    println!("Score: {}", score);
-                    scores.push(score);
+                let status = chunks.classify(&strval);
+                if diagnostics {
+                    if let Some(d) = (Diagnostic { file: filename, line: n + 1, text: &strval }).render(&chunks, &status) {
+                        println!("{}\n", d);
+                    }
+                }
+                if fix {
+                    let (edits, minimal) = chunks.repair(&strval, &status);
+                    if !minimal {
+                        println!("Line {}: more than {} delimiters, repaired greedily rather than minimally", n + 1, MAX_REPAIR_TOKENS);
+                    }
+                    for edit in &edits {
+                        println!("Line {}: {}", n + 1, edit.describe(&strval));
+                    }
+                    fixed.push(Edit::apply(&strval, &edits));
+                }
+                if let LineStatus::Incomplete { .. } = status {
+                    match chunks.calculate(&status) {
+                        Some(score) => scores.push(score),
+                        None => println!("{}:{}: completion score is too large, line skipped", filename, n + 1)
+                    }
                 }
             }
         }
+        if fix {
+            let path = format!("{}.fixed", filename);
+            let written = File::create(&path).and_then(|mut f| f.write_all((fixed.join("\n") + "\n").as_bytes()));
+            match written {
+                Ok(_) => println!("Wrote {:?}", path),
+                Err(e) => println!("Failed to write {:?}: {}", path, e)
+            }
+        }
         scores.sort();
 
 //        println!("Scores: {:?}", scores);
-        println!("Result: {}", scores[(scores.len() - 1) / 2]);
+        if scores.is_empty() {
+            println!("No incomplete lines");
+        } else {
+            println!("Result: {}", scores[(scores.len() - 1) / 2]);
+        }
     }
 }
 
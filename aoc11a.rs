@@ -8,11 +8,17 @@ use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
 
+// Two bytes per cell keeps large grids compact
+type Energy = i16;
+// A cell can gain one from the step and one from each of its eight neighbours before it flashes
+const MAX_ENERGY: Energy = Energy::MAX - 9;
+
 struct Floor {
-    temp: String,
-    floor: Vec<usize>,
+    floor: Vec<Energy>,
     width: usize,
     depth: usize,
+    // Whether rows are separated integers rather than runs of digits
+    integers: bool,
     area: usize,
     flashes: usize
 
@@ -21,31 +27,67 @@ struct Floor {
 impl Floor {
     fn new() -> Floor {
         Floor{
-            temp: String::from(""),
             floor: Vec::new(),
             width: 0,
             depth: 0,
+            integers: false,
             area: 0,
             flashes: 0
         }
     }
 
-    fn add(&mut self, str: &str) {
+    // Integer grids are told apart from runs of digits by a separator or minus sign
+    // anywhere in the file, or by rows of different lengths, which a grid of digits
+    // can't have. Deciding once means a single column such as 120 or -5 reads the
+    // same as the other rows
+    fn separated(rows: &[io::Result<String>]) -> bool {
+        let rows: Vec<&str> = rows.iter().flatten().map(|row| row.trim()).filter(|row| !row.is_empty()).collect();
+        rows.iter().any(|row| row.contains(|c: char| c == ',' || c == '-' || c.is_whitespace()))
+            || rows.iter().any(|row| row.len() != rows[0].len())
+    }
+
+    // Rows are either runs of single digits or whitespace/comma separated integers
+    fn parse_row(str: &str, integers: bool) -> Result<Vec<Energy>, String> {
+        const RADIX: u32 = 10;
+        if integers {
+            str.split(|c: char| c == ',' || c.is_whitespace())
+               .filter(|t| !t.is_empty())
+               .map(|t| match t.parse::<Energy>() {
+                   Ok(v) if v < 0 => Err(format!("energy {} is negative", v)),
+                   Ok(v) if v > MAX_ENERGY => Err(format!("energy {} is above the limit of {}", v, MAX_ENERGY)),
+                   Ok(v) => Ok(v),
+                   Err(e) => Err(format!("invalid energy {:?}: {}", t, e))
+               })
+               .collect()
+        } else {
+            str.trim().chars()
+               .map(|c| c.to_digit(RADIX).map(|d| d as Energy).ok_or(format!("invalid digit {:?}", c)))
+               .collect()
+        }
+    }
+
+    fn add(&mut self, str: &str) -> Result<(), String> {
+        if str.trim() == "" {
+            return Ok(());
+        }
+        let row = Floor::parse_row(str, self.integers)?;
         if self.width == 0 {
-            self.width = str.len();
+            self.width = row.len();
+        } else if row.len() != self.width {
+            return Err(format!("expected {} values but found {}", self.width, row.len()));
         }
         self.depth += 1;
-        self.temp.push_str(str);
+        self.floor.extend(row);
+        Ok(())
     }
 
     fn convert(&mut self) {
-        const RADIX: u32 = 10;
-        if self.temp != "" {
-            self.area = self.temp.len();
-            self.floor = self.temp.chars().map(|c| c.to_digit(RADIX).unwrap() as usize).collect();
-            self.temp.clear();
-        }
+        self.area = self.floor.len();
+    }
 
+    // Width of the widest value, so multi-digit grids line up when displayed
+    fn cell_width(&self) -> usize {
+        self.floor.iter().map(|e| e.to_string().len()).max().unwrap_or(1)
     }
 
     // Returns the vector of adjacent points
@@ -108,15 +150,19 @@ impl Floor {
 impl fmt::Display for Floor {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.write_fmt(format_args!("{} = {} * {}\n", self.area, self.width, self.depth))?;
+        let w = self.cell_width();
         for i in (0..self.area).step_by(self.width as usize) {
             fmt.write_fmt(format_args!("{:03}: ", i))?;
             for j in i..i+self.width {
+                if w > 1 && j > i {
+                    fmt.write_str(" ")?;
+                }
                 if self.floor[j] == 0 {
-                    fmt.write_fmt(format_args!("{}{}{}", style::Bold, self.floor[j], style::Reset))?;
+                    fmt.write_fmt(format_args!("{}{:>w$}{}", style::Bold, self.floor[j], style::Reset, w = w))?;
                 } else if self.floor[j] > 9 {
-                    fmt.write_fmt(format_args!("{}*{}", color::Fg(color::Yellow), color::Fg(color::Reset)))?;
+                    fmt.write_fmt(format_args!("{}{:>w$}{}", color::Fg(color::Yellow), "*", color::Fg(color::Reset), w = w))?;
                 } else {
-                    fmt.write_fmt(format_args!("{}{}{}", color::Fg(color::Yellow), self.floor[j], color::Fg(color::Reset)))?;
+                    fmt.write_fmt(format_args!("{}{:>w$}{}", color::Fg(color::Yellow), self.floor[j], color::Fg(color::Reset), w = w))?;
                 }
             }
             fmt.write_str("\n")?;
@@ -137,9 +183,14 @@ fn main() {
     println!("Reading {:?}", &args[1]);
     if let Ok(lines) = read_lines(&args[1]) {
         let mut floor = Floor::new();
-        for line in lines {
+        let lines: Vec<io::Result<String>> = lines.collect();
+        floor.integers = Floor::separated(&lines);
+        for (n, line) in lines.into_iter().enumerate() {
             if let Ok(strval) = line {
-                floor.add(&strval);
+                if let Err(e) = floor.add(&strval) {
+                    println!("Line {}: {}", n + 1, e);
+                    return;
+                }
             }
         }
         floor.convert();
@@ -1,72 +1,216 @@
 extern crate termion;
 
-use termion::{color, style};
+use termion::{clear, color, cursor, style};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
 
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
 use std::env;
 use std::fmt;
 use std::fs::File;
-use std::io::{self, BufRead};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, Write};
 use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-struct Floor {
-    temp: String,
-    floor: Vec<usize>,
+// Two bytes per cell keeps large grids compact
+type Energy = i16;
+// A cell can gain one from the step and one from each of its eight neighbours before it flashes
+const MAX_ENERGY: Energy = Energy::MAX - 9;
+// Far more cascade rounds than any of the rules should need to settle a step
+const MAX_ROUNDS: usize = 1_000_000;
+
+// What lies beyond the edge of the grid
+#[derive(Clone, Copy, PartialEq)]
+enum Boundary {
+    // Nothing: edge cells just have fewer neighbours
+    Clip,
+    // The grid is a torus, so each edge neighbours the opposite one
+    Wrap,
+    // The edge row or column is duplicated outside the grid
+    Reflect,
+    // Ghost cells that hold a fixed value and never change
+    Pad(Energy)
+}
+
+impl Boundary {
+    fn parse(spec: &str) -> Result<Boundary, String> {
+        match spec {
+            "clip" => Ok(Boundary::Clip),
+            "wrap" => Ok(Boundary::Wrap),
+            "reflect" => Ok(Boundary::Reflect),
+            _ if spec.starts_with("pad=") => spec[4..].parse::<Energy>()
+                .map(Boundary::Pad)
+                .map_err(|e| format!("invalid padding {:?}: {}", &spec[4..], e)),
+            _ => Err(format!("unknown boundary {:?}, expected clip, wrap, reflect or pad=N", spec))
+        }
+    }
+}
+
+impl fmt::Display for Boundary {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Boundary::Clip => fmt.write_str("clip"),
+            Boundary::Wrap => fmt.write_str("wrap"),
+            Boundary::Reflect => fmt.write_str("reflect"),
+            Boundary::Pad(v) => fmt.write_fmt(format_args!("pad={}", v))
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Floor<T> {
+    floor: Vec<T>,
     width: usize,
     depth: usize,
+    // Whether rows are separated integers rather than runs of digits
+    integers: bool,
     area: usize,
-    flashes: usize
-
+    flashes: usize,
+    boundary: Boundary,
+    // Cells due to fire in the next cascade round, so the octopus rule needn't rescan the grid
+    pending: Vec<usize>,
+    // Number of row bands the octopus rule splits the grid into
+    threads: usize
 }
 
-impl Floor {
-    fn new() -> Floor {
+impl Floor<Energy> {
+    fn new() -> Floor<Energy> {
         Floor{
-            temp: String::from(""),
             floor: Vec::new(),
             width: 0,
             depth: 0,
+            integers: false,
             area: 0,
-            flashes: 0
+            flashes: 0,
+            boundary: Boundary::Clip,
+            pending: Vec::new(),
+            threads: 1
         }
     }
 
-    fn add(&mut self, str: &str) {
+    // Integer grids are told apart from runs of digits by a separator or minus sign
+    // anywhere in the file, or by rows of different lengths, which a grid of digits
+    // can't have. Deciding once means a single column such as 120 or -5 reads the
+    // same as the other rows
+    fn separated(rows: &[io::Result<String>]) -> bool {
+        let rows: Vec<&str> = rows.iter().flatten().map(|row| row.trim()).filter(|row| !row.is_empty()).collect();
+        rows.iter().any(|row| row.contains(|c: char| c == ',' || c == '-' || c.is_whitespace()))
+            || rows.iter().any(|row| row.len() != rows[0].len())
+    }
+
+    // Rows are either runs of single digits or whitespace/comma separated integers
+    fn parse_row(str: &str, integers: bool) -> Result<Vec<Energy>, String> {
+        const RADIX: u32 = 10;
+        if integers {
+            str.split(|c: char| c == ',' || c.is_whitespace())
+               .filter(|t| !t.is_empty())
+               .map(|t| match t.parse::<Energy>() {
+                   Ok(v) if v < 0 => Err(format!("energy {} is negative", v)),
+                   Ok(v) if v > MAX_ENERGY => Err(format!("energy {} is above the limit of {}", v, MAX_ENERGY)),
+                   Ok(v) => Ok(v),
+                   Err(e) => Err(format!("invalid energy {:?}: {}", t, e))
+               })
+               .collect()
+        } else {
+            str.trim().chars()
+               .map(|c| c.to_digit(RADIX).map(|d| d as Energy).ok_or(format!("invalid digit {:?}", c)))
+               .collect()
+        }
+    }
+
+    fn add(&mut self, str: &str) -> Result<(), String> {
+        if str.trim() == "" {
+            return Ok(());
+        }
+        let row = Floor::parse_row(str, self.integers)?;
         if self.width == 0 {
-            self.width = str.len();
+            self.width = row.len();
+        } else if row.len() != self.width {
+            return Err(format!("expected {} values but found {}", self.width, row.len()));
         }
         self.depth += 1;
-        self.temp.push_str(str);
+        self.floor.extend(row);
+        Ok(())
     }
 
     fn convert(&mut self) {
-        const RADIX: u32 = 10;
-        if self.temp != "" {
-            self.area = self.temp.len();
-            self.floor = self.temp.chars().map(|c| c.to_digit(RADIX).unwrap() as usize).collect();
-            self.temp.clear();
+        self.area = self.floor.len();
+    }
+}
+
+impl<T: Copy> Floor<T> {
+    // The same grid with every cell converted to another state
+    fn map<U>(&self, f: impl Fn(T) -> U) -> Floor<U> {
+        Floor {
+            floor: self.floor.iter().map(|c| f(*c)).collect(),
+            width: self.width,
+            depth: self.depth,
+            integers: self.integers,
+            area: self.area,
+            flashes: 0,
+            boundary: self.boundary,
+            pending: Vec::new(),
+            threads: self.threads
         }
+    }
+
+    // Returns the vector of adjacent points. Wrapped and reflected edges can
+    // list the same cell more than once, or the cell itself
+    fn adjacent(&self, pos: usize, diagonal: bool) -> Vec<usize> {
+        match self.boundary {
+            Boundary::Clip | Boundary::Pad(_) => self.clipped(pos, diagonal),
+            _ => {
+                let x = (pos % self.width) as isize;
+                let y = (pos / self.width) as isize;
+                let offsets: &[(isize, isize)] = if diagonal {
+                    &[(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)]
+                } else {
+                    &[(0, -1), (-1, 0), (1, 0), (0, 1)]
+                };
+                offsets.iter().map(|(dx, dy)| {
+                    let (nx, ny) = (self.edge(x + dx, self.width), self.edge(y + dy, self.depth));
+                    ny * self.width + nx
+                }).collect()
+            }
+        }
+    }
 
+    // Brings a coordinate that has stepped off the grid back onto it
+    fn edge(&self, v: isize, size: usize) -> usize {
+        match self.boundary {
+            Boundary::Wrap => v.rem_euclid(size as isize) as usize,
+            _ => v.max(0).min(size as isize - 1) as usize
+        }
     }
 
-    // Returns the vector of adjacent points
-    fn adjacent(&self, pos: usize) -> Vec<usize> {
+    // Number of neighbours that fall outside the grid
+    fn ghosts(&self, pos: usize, diagonal: bool) -> usize {
+        (if diagonal { 8 } else { 4 }) - self.clipped(pos, diagonal).len()
+    }
+
+    fn clipped(&self, pos: usize, diagonal: bool) -> Vec<usize> {
         let x: usize = pos % self.width;
         let mut result: Vec<usize> = Vec::new();
         if pos >= self.width {
             result.push(pos - self.width);
-            if x > 0 {
+            if diagonal && x > 0 {
                 result.push(pos - self.width - 1);
             }
-            if x < self.width - 1 {
+            if diagonal && x < self.width - 1 {
                 result.push(pos - self.width + 1);
             }
         }
         if pos < self.area - self.width {
             result.push(pos + self.width);
-            if x > 0 {
+            if diagonal && x > 0 {
                 result.push(pos + self.width - 1);
             }
-            if x < self.width - 1 {
+            if diagonal && x < self.width - 1 {
                 result.push(pos + self.width + 1);
             }
         }
@@ -79,45 +223,329 @@ impl Floor {
         result
     }
 
-    fn energise(&mut self) {
-        self.floor.iter_mut().for_each(|x| *x += 1);
+    // Fingerprint of the cell states, ignoring the event count
+    fn fingerprint(&self) -> u64 where T: Hash {
+        let mut hasher = DefaultHasher::new();
+        self.floor.hash(&mut hasher);
+        hasher.finish()
     }
 
-    fn flash(&mut self) -> bool {
-        let mut flashes: usize = 0;
-        let mut f: Vec<usize> = Vec::new();
-        for i in (0..self.area).step_by(self.width as usize) {
-            for j in i..i+self.width {
-                if self.floor[j] > 9 {
-                    f.push(j);
-                    flashes += 1;
-                    self.floor[j] = 0;
-                    for a in self.adjacent(j) {
-                        if self.floor[a] > 0 {
-                            self.floor[a] += 1;
+    // Runs one step of the rule and its cascade, returning the cells that fired
+    // and the number of cascade rounds in which something fired
+    fn advance<R: CellularAutomaton<State = T>>(&mut self, rule: &R) -> Result<(Vec<usize>, usize), String> {
+        let mut fired = rule.step(self);
+        let mut rounds = 0;
+        loop {
+            let round = rule.settle(self);
+            if round.is_empty() {
+                break;
+            }
+            rounds += 1;
+            if rounds > MAX_ROUNDS {
+                return Err(format!("the cascade didn't settle within {} rounds", MAX_ROUNDS));
+            }
+            fired.extend(round);
+        }
+        self.flashes = fired.len();
+        Ok((fired, rounds))
+    }
+}
+
+// How a cell state is drawn in the grid
+trait Glyph {
+    fn label(&self) -> String;
+    fn paint(&self, label: &str) -> String;
+}
+
+impl Glyph for Energy {
+    fn label(&self) -> String {
+        if *self > 9 { String::from("*") } else { self.to_string() }
+    }
+
+    fn paint(&self, label: &str) -> String {
+        if *self == 0 {
+            format!("{}{}{}", style::Bold, label, style::Reset)
+        } else {
+            format!("{}{}{}", color::Fg(color::Yellow), label, color::Fg(color::Reset))
+        }
+    }
+}
+
+impl Glyph for bool {
+    fn label(&self) -> String {
+        String::from(if *self { "#" } else { "." })
+    }
+
+    fn paint(&self, label: &str) -> String {
+        if *self {
+            format!("{}{}{}{}", style::Bold, color::Fg(color::Green), label, style::Reset)
+        } else {
+            String::from(label)
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Hash)]
+struct Grains(Energy);
+
+impl Glyph for Grains {
+    fn label(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn paint(&self, label: &str) -> String {
+        match self.0 {
+            0 => String::from(label),
+            1 => format!("{}{}{}", color::Fg(color::Blue), label, color::Fg(color::Reset)),
+            2 => format!("{}{}{}", color::Fg(color::Cyan), label, color::Fg(color::Reset)),
+            3 => format!("{}{}{}", color::Fg(color::Yellow), label, color::Fg(color::Reset)),
+            _ => format!("{}{}{}", color::Fg(color::Red), label, color::Fg(color::Reset))
+        }
+    }
+}
+
+// A rule applied to every cell of the floor each step, followed by a cascade
+// that repeats until nothing more fires
+trait CellularAutomaton {
+    type State: Copy + PartialEq + Hash + Glyph;
+
+    // What the rule counts as an event, for reporting
+    fn event(&self) -> &'static str;
+    // The starting state for a value read from the input
+    fn load(&self, value: Energy) -> Self::State;
+    // Whether diagonal cells are neighbours
+    fn diagonal(&self) -> bool;
+    // Updates every cell, returning the cells that fired
+    fn step(&self, floor: &mut Floor<Self::State>) -> Vec<usize>;
+    // Runs one round of the cascade, returning the cells that fired
+    fn settle(&self, floor: &mut Floor<Self::State>) -> Vec<usize>;
+}
+
+// Every octopus gains energy each step and flashes above 9, energising its neighbours
+struct Octopus;
+
+impl CellularAutomaton for Octopus {
+    type State = Energy;
+
+    fn event(&self) -> &'static str {
+        "flashes"
+    }
+
+    fn load(&self, value: Energy) -> Energy {
+        value
+    }
+
+    fn diagonal(&self) -> bool {
+        true
+    }
+
+    fn step(&self, floor: &mut Floor<Energy>) -> Vec<usize> {
+        let size = Octopus::band(floor);
+        let charged: Vec<Vec<usize>> = if floor.threads > 1 {
+            thread::scope(|scope| {
+                let handles: Vec<_> = floor.floor.chunks_mut(size).enumerate()
+                    .map(|(k, chunk)| scope.spawn(move || Octopus::charge(k * size, chunk)))
+                    .collect();
+                handles.into_iter().map(|h| h.join().unwrap()).collect()
+            })
+        } else {
+            vec![Octopus::charge(0, &mut floor.floor)]
+        };
+        floor.pending = charged.concat();
+        // Padding above 9 flashes on every step, energising the cells along the edge
+        if let Boundary::Pad(v) = floor.boundary {
+            if v > 9 {
+                for pos in 0..floor.area {
+                    let ghosts = floor.ghosts(pos, self.diagonal()) as Energy;
+                    if ghosts > 0 {
+                        let before = floor.floor[pos];
+                        floor.floor[pos] += ghosts;
+                        if before <= 9 && floor.floor[pos] > 9 {
+                            floor.pending.push(pos);
                         }
                     }
                 }
             }
         }
-        self.flashes += flashes;
-        return flashes > 0;
+        Vec::new()
+    }
+
+    // Every pending octopus flashes at once, then their neighbours are energised.
+    // Energy only ever goes up by one at a time, so each neighbour that reaches
+    // 10 is queued exactly once and the order the bands finish in doesn't matter
+    fn settle(&self, floor: &mut Floor<Energy>) -> Vec<usize> {
+        let mut fired = std::mem::take(&mut floor.pending);
+        fired.sort();
+        fired.dedup();
+        for pos in &fired {
+            floor.floor[*pos] = 0;
+        }
+        let size = Octopus::band(floor);
+        let shape = floor.map(|_| ());
+        let results: Vec<(Vec<usize>, Vec<usize>)> = if floor.threads > 1 {
+            let mut bands: Vec<Vec<usize>> = vec![Vec::new(); floor.area.div_ceil(size)];
+            for pos in &fired {
+                bands[pos / size].push(*pos);
+            }
+            let shape = &shape;
+            thread::scope(|scope| {
+                let handles: Vec<_> = floor.floor.chunks_mut(size).zip(bands.iter()).enumerate()
+                    .map(|(k, (chunk, cells))| scope.spawn(move || Octopus::energise(shape, k * size, chunk, cells)))
+                    .collect();
+                handles.into_iter().map(|h| h.join().unwrap()).collect()
+            })
+        } else {
+            vec![Octopus::energise(&shape, 0, &mut floor.floor, &fired)]
+        };
+        // Neighbours in other bands are energised afterwards, in band order
+        for (next, outbox) in results {
+            floor.pending.extend(next);
+            for a in outbox {
+                if floor.floor[a] > 0 {
+                    floor.floor[a] += 1;
+                    if floor.floor[a] == 10 {
+                        floor.pending.push(a);
+                    }
+                }
+            }
+        }
+        fired
+    }
+}
+
+impl Octopus {
+    // Cells per band, in whole rows
+    fn band(floor: &Floor<Energy>) -> usize {
+        let rows = floor.depth.div_ceil(floor.threads);
+        rows.max(1) * floor.width
+    }
+
+    // Adds one to each cell of the band, returning those ready to flash
+    fn charge(base: usize, chunk: &mut [Energy]) -> Vec<usize> {
+        let mut ready: Vec<usize> = Vec::new();
+        for (i, x) in chunk.iter_mut().enumerate() {
+            *x += 1;
+            if *x > 9 {
+                ready.push(base + i);
+            }
+        }
+        ready
+    }
+
+    // Energises the neighbours of the band's flashing cells that lie inside the band,
+    // returning the cells that reached 10 and the neighbours that lie outside it
+    fn energise(shape: &Floor<()>, base: usize, chunk: &mut [Energy], cells: &[usize]) -> (Vec<usize>, Vec<usize>) {
+        let mut next: Vec<usize> = Vec::new();
+        let mut outbox: Vec<usize> = Vec::new();
+        for pos in cells {
+            for a in shape.adjacent(*pos, true) {
+                if a < base || a >= base + chunk.len() {
+                    outbox.push(a);
+                } else if chunk[a - base] > 0 {
+                    chunk[a - base] += 1;
+                    if chunk[a - base] == 10 {
+                        next.push(a);
+                    }
+                }
+            }
+        }
+        (next, outbox)
     }
 }
 
-impl fmt::Display for Floor {
+// Conway's Life, reading any non-zero input value as a live cell
+struct Life;
+
+impl CellularAutomaton for Life {
+    type State = bool;
+
+    fn event(&self) -> &'static str {
+        "births"
+    }
+
+    fn load(&self, value: Energy) -> bool {
+        value != 0
+    }
+
+    fn diagonal(&self) -> bool {
+        true
+    }
+
+    fn step(&self, floor: &mut Floor<bool>) -> Vec<usize> {
+        let mut births: Vec<usize> = Vec::new();
+        let next: Vec<bool> = (0..floor.area).map(|pos| {
+            let mut alive = floor.adjacent(pos, self.diagonal()).into_iter().filter(|a| floor.floor[*a]).count();
+            if let Boundary::Pad(v) = floor.boundary {
+                if self.load(v) {
+                    alive += floor.ghosts(pos, self.diagonal());
+                }
+            }
+            let live = alive == 3 || (alive == 2 && floor.floor[pos]);
+            if live && !floor.floor[pos] {
+                births.push(pos);
+            }
+            live
+        }).collect();
+        floor.floor = next;
+        births
+    }
+
+    fn settle(&self, _floor: &mut Floor<bool>) -> Vec<usize> {
+        Vec::new()
+    }
+}
+
+// Abelian sandpile: a grain is dropped in the middle each step and any cell
+// holding four grains topples one onto each neighbour. Grains that fall off the
+// edge are lost whatever the boundary, otherwise a full enough pile never settles
+struct Sandpile;
+
+impl CellularAutomaton for Sandpile {
+    type State = Grains;
+
+    fn event(&self) -> &'static str {
+        "topples"
+    }
+
+    fn load(&self, value: Energy) -> Grains {
+        Grains(value)
+    }
+
+    fn diagonal(&self) -> bool {
+        false
+    }
+
+    fn step(&self, floor: &mut Floor<Grains>) -> Vec<usize> {
+        let centre = (floor.depth / 2) * floor.width + floor.width / 2;
+        floor.floor[centre].0 += 1;
+        Vec::new()
+    }
+
+    fn settle(&self, floor: &mut Floor<Grains>) -> Vec<usize> {
+        let toppled: Vec<usize> = (0..floor.area).filter(|pos| floor.floor[*pos].0 >= 4).collect();
+        for pos in &toppled {
+            floor.floor[*pos].0 -= 4;
+            for a in floor.clipped(*pos, self.diagonal()) {
+                floor.floor[a].0 += 1;
+            }
+        }
+        toppled
+    }
+}
+
+impl<T: Copy + Glyph> fmt::Display for Floor<T> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.write_fmt(format_args!("{} = {} * {}\n", self.area, self.width, self.depth))?;
+        // Width of the widest label, so multi-digit grids line up
+        let w = self.floor.iter().map(|c| c.label().len()).max().unwrap_or(1);
         for i in (0..self.area).step_by(self.width as usize) {
             fmt.write_fmt(format_args!("{:03}: ", i))?;
             for j in i..i+self.width {
-                if self.floor[j] == 0 {
-                    fmt.write_fmt(format_args!("{}{}{}", style::Bold, self.floor[j], style::Reset))?;
-                } else if self.floor[j] > 9 {
-                    fmt.write_fmt(format_args!("{}*{}", color::Fg(color::Yellow), color::Fg(color::Reset)))?;
-                } else {
-                    fmt.write_fmt(format_args!("{}{}{}", color::Fg(color::Yellow), self.floor[j], color::Fg(color::Reset)))?;
+                if w > 1 && j > i {
+                    fmt.write_str(" ")?;
                 }
+                let c = self.floor[j];
+                fmt.write_str(&c.paint(&format!("{:>w$}", c.label(), w = w)))?;
             }
             fmt.write_str("\n")?;
         }
@@ -125,6 +553,444 @@ impl fmt::Display for Floor {
     }
 }
 
+// Which cells fired on which steps, and how long each step's cascade ran
+struct Statistics {
+    width: usize,
+    counts: Vec<usize>,
+    fired: Vec<Vec<usize>>,
+    // (events, cascade rounds) for each step
+    steps: Vec<(usize, usize)>
+}
+
+impl Statistics {
+    fn new<T>(floor: &Floor<T>) -> Statistics {
+        Statistics {
+            width: floor.width,
+            counts: vec![0; floor.area],
+            fired: vec![Vec::new(); floor.area],
+            steps: Vec::new()
+        }
+    }
+
+    fn record(&mut self, step: usize, fired: &Vec<usize>, rounds: usize) {
+        for pos in fired {
+            self.counts[*pos] += 1;
+            self.fired[*pos].push(step);
+        }
+        self.steps.push((fired.len(), rounds));
+    }
+
+    // Counts shaded from blue for the quietest cells to red for the busiest
+    fn heatmap(&self) -> String {
+        let low = self.counts.iter().min().cloned().unwrap_or(0);
+        let high = self.counts.iter().max().cloned().unwrap_or(0);
+        let w = high.to_string().len();
+        let mut result = format!("Heatmap: {} to {} events per cell\n", low, high);
+        for i in (0..self.counts.len()).step_by(self.width) {
+            result += &format!("{:03}: ", i);
+            for j in i..i+self.width {
+                let t = if high > low { (self.counts[j] - low) as f64 / (high - low) as f64 } else { 1.0 };
+                let shade = color::Rgb((255.0 * t) as u8, 64, (255.0 * (1.0 - t)) as u8);
+                result += &format!("{}{}{:>w$} {}", color::Bg(shade), color::Fg(color::White), self.counts[j], style::Reset, w = w);
+            }
+            result += "\n";
+        }
+        result
+    }
+
+    fn write_csv(&self, path: &str) -> io::Result<()> {
+        let mut out = io::BufWriter::new(File::create(path)?);
+        writeln!(out, "step,flashes,rounds")?;
+        for (step, (flashes, rounds)) in self.steps.iter().enumerate() {
+            writeln!(out, "{},{},{}", step + 1, flashes, rounds)?;
+        }
+        out.flush()
+    }
+
+    // One row per cell with the steps it fired on, space separated
+    fn write_cells(&self, path: &str) -> io::Result<()> {
+        let mut out = io::BufWriter::new(File::create(path)?);
+        writeln!(out, "x,y,count,steps")?;
+        for (pos, steps) in self.fired.iter().enumerate() {
+            let list: Vec<String> = steps.iter().map(|s| s.to_string()).collect();
+            writeln!(out, "{},{},{},{}", pos % self.width, pos / self.width, self.counts[pos], list.join(" "))?;
+        }
+        out.flush()
+    }
+}
+
+// Writes frames as an asciinema v2 recording: a JSON header line followed by
+// one [time, "o", data] line per frame
+struct Recording {
+    out: io::BufWriter<File>,
+    interval: f64,
+    frames: usize
+}
+
+impl Recording {
+    fn new(path: &str, width: usize, height: usize, fps: u32) -> io::Result<Recording> {
+        let mut out = io::BufWriter::new(File::create(path)?);
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        writeln!(out, "{{\"version\": 2, \"width\": {}, \"height\": {}, \"timestamp\": {}, \"env\": {{\"TERM\": \"xterm-256color\"}}}}",
+                 width, height, timestamp)?;
+        Ok(Recording {
+            out,
+            interval: 1.0 / fps as f64,
+            frames: 0
+        })
+    }
+
+    // JSON only allows a few escapes, so control characters such as ESC become \u00XX
+    fn escape(text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                '"' => result.push_str("\\\""),
+                '\\' => result.push_str("\\\\"),
+                '\n' => result.push_str("\\n"),
+                '\r' => result.push_str("\\r"),
+                '\t' => result.push_str("\\t"),
+                c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+                c => result.push(c)
+            }
+        }
+        result
+    }
+
+    fn frame(&mut self, title: &str, grid: &str) -> io::Result<()> {
+        let data = format!("{}{}{}\r\n{}", clear::All, cursor::Goto(1, 1), title, grid.replace("\n", "\r\n"));
+        writeln!(self.out, "[{:.3}, \"o\", \"{}\"]", self.frames as f64 * self.interval, Recording::escape(&data))?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+struct Options {
+    steps: Option<usize>,
+    max_steps: usize,
+    heatmap: bool,
+    csv: Option<String>,
+    cells: Option<String>,
+    cast: Option<String>,
+    fps: u32,
+    quiet: bool
+}
+
+// States seen so far, filed by fingerprint. A matching fingerprint is only a
+// candidate: the run is replayed to that step to confirm the states really match
+struct History<T> {
+    initial: Floor<T>,
+    seen: HashMap<u64, Vec<usize>>
+}
+
+impl<T: Copy + PartialEq + Hash> History<T> {
+    fn new(initial: &Floor<T>) -> History<T> {
+        let mut seen: HashMap<u64, Vec<usize>> = HashMap::new();
+        seen.insert(initial.fingerprint(), vec![0]);
+        History {
+            initial: initial.clone(),
+            seen
+        }
+    }
+
+    // The earlier step with the same state as the floor, if there is one
+    fn repeat<R: CellularAutomaton<State = T>>(&mut self, rule: &R, floor: &Floor<T>, step: usize) -> Option<usize> {
+        let candidates = self.seen.entry(floor.fingerprint()).or_default();
+        for earlier in candidates.iter() {
+            let mut replay = self.initial.clone();
+            // These steps already ran once, so they settle again
+            for _i in 0..*earlier {
+                let _ = replay.advance(rule);
+            }
+            if replay.floor == floor.floor {
+                return Some(*earlier);
+            }
+        }
+        candidates.push(step);
+        None
+    }
+}
+
+// Runs the rule for a fixed number of steps, or until every cell fires in the same step.
+// The rules are deterministic, so once a state repeats the run is periodic and a grid
+// that hasn't synchronised by then never will
+fn run<R: CellularAutomaton>(rule: R, input: &Floor<Energy>, options: &Options) -> io::Result<()> {
+    let started = Instant::now();
+    let mut floor = input.map(|v| rule.load(v));
+    let mut stats = Statistics::new(&floor);
+    let mut recording = match options.cast {
+        Some(ref path) => {
+            // Room for the row prefix and a header line above the rows
+            let cell = floor.floor.iter().map(|c| c.label().len()).max().unwrap_or(1) + 1;
+            let mut r = Recording::new(path, 5 + floor.width * cell, floor.depth + 2, options.fps)?;
+            r.frame("Initial state:", &floor.to_string())?;
+            Some(r)
+        },
+        None => None
+    };
+    let mut history = History::new(&floor);
+    let mut synchronised: Vec<usize> = Vec::new();
+    let mut cycle: Option<(usize, usize)> = None;
+    let mut step = 0;
+    let mut total = 0;
+    loop {
+        let (fired, rounds) = match floor.advance(&rule) {
+            Ok(result) => result,
+            Err(e) => {
+                println!("Step {}: {}", step, e);
+                break;
+            }
+        };
+        total += fired.len();
+        if !options.quiet {
+            println!("Step {}:\n{}", step, floor);
+        }
+        if let Some(ref mut r) = recording {
+            r.frame(&format!("Step {}: {} {}", step, floor.flashes, rule.event()), &floor.to_string())?;
+        }
+        step += 1;
+        if options.heatmap || options.csv.is_some() || options.cells.is_some() {
+            stats.record(step, &fired, rounds);
+        }
+        if floor.flashes == floor.area {
+            synchronised.push(step);
+        }
+        if cycle.is_none() {
+            if let Some(start) = history.repeat(&rule, &floor, step) {
+                let possible = synchronised.iter().any(|s| *s > start);
+                println!("Cycle found: pre-period {} steps, period {} steps, synchronisation {}",
+                         start, step - start, if possible { "recurs" } else { "never occurs" });
+                cycle = Some((start, step - start));
+            }
+        }
+        match options.steps {
+            Some(n) if step >= n => {
+                println!("Total {} after {} steps = {}", rule.event(), step, total);
+                break;
+            },
+            None if floor.flashes == floor.area => {
+                println!("Synchronised flash after {} steps", step);
+                break;
+            },
+            None if cycle.is_some() => {
+                println!("No synchronised flash: the grid repeats without every cell firing together");
+                break;
+            },
+            _ => {}
+        }
+        // An explicit step count overrides the cap
+        if options.steps.is_none() && step >= options.max_steps {
+            println!("Stopped after {} steps without a synchronised flash or a cycle", step);
+            break;
+        }
+    }
+    println!("Elapsed: {:.3?} on {} thread(s)", started.elapsed(), floor.threads);
+    if let Some(ref mut r) = recording {
+        r.finish()?;
+        println!("Recorded {} frames to {}", r.frames, options.cast.as_ref().map_or("", |p| p.as_str()));
+    }
+    if options.heatmap {
+        print!("{}", stats.heatmap());
+    }
+    if let Some(ref path) = options.csv {
+        stats.write_csv(path)?;
+        println!("Wrote step statistics to {}", path);
+    }
+    if let Some(ref path) = options.cells {
+        stats.write_cells(path)?;
+        println!("Wrote cell statistics to {}", path);
+    }
+    Ok(())
+}
+
+// Runs the rule quietly under each boundary and reports when every cell first fires together
+fn compare<R: CellularAutomaton>(rule: R, input: &Floor<Energy>, boundaries: &[Boundary], max_steps: usize) {
+    for boundary in boundaries {
+        let mut floor = input.map(|v| rule.load(v));
+        floor.boundary = *boundary;
+        let mut history = History::new(&floor);
+        let mut step = 0;
+        let outcome = loop {
+            if let Err(e) = floor.advance(&rule) {
+                break format!("{} on step {}", e, step + 1);
+            }
+            step += 1;
+            if floor.flashes == floor.area {
+                break format!("all cells fire together after {} steps", step);
+            }
+            if let Some(start) = history.repeat(&rule, &floor, step) {
+                break format!("never, repeats every {} steps from step {}", step - start, start);
+            }
+            if step >= max_steps {
+                break format!("not within {} steps", max_steps);
+            }
+        };
+        println!("{:>8}: {}", boundary.to_string(), outcome);
+    }
+}
+
+// Redraws the floor in place on the alternate screen, stepping on a timer or on demand
+struct Player<R: CellularAutomaton> {
+    rule: R,
+    initial: Floor<R::State>,
+    floor: Floor<R::State>,
+    steps: Option<usize>,
+    step: usize,
+    total: usize,
+    fps: u32,
+    playing: bool,
+    // Digits typed after 'g', while a jump target is being entered
+    jump: Option<String>,
+    finished: bool,
+    // Set when a step's cascade never settled
+    stalled: bool
+}
+
+impl<R: CellularAutomaton> Player<R> {
+    fn new(rule: R, input: &Floor<Energy>, steps: Option<usize>, fps: u32) -> Player<R> {
+        let floor = input.map(|v| rule.load(v));
+        Player {
+            rule,
+            initial: floor.clone(),
+            floor,
+            steps,
+            step: 0,
+            total: 0,
+            fps,
+            playing: false,
+            jump: None,
+            finished: false,
+            stalled: false
+        }
+    }
+
+    fn advance(&mut self) {
+        if self.finished {
+            return;
+        }
+        let fired = match self.floor.advance(&self.rule) {
+            Ok((fired, _)) => fired,
+            Err(_) => {
+                self.stalled = true;
+                self.finished = true;
+                self.playing = false;
+                return;
+            }
+        };
+        self.total += fired.len();
+        self.step += 1;
+        self.finished = match self.steps {
+            Some(n) => self.step >= n,
+            None => self.floor.flashes == self.floor.area
+        };
+        if self.finished {
+            self.playing = false;
+        }
+    }
+
+    // Steps can't be undone, so going backwards replays from the start
+    fn seek(&mut self, target: usize) {
+        if target < self.step {
+            self.floor = self.initial.clone();
+            self.step = 0;
+            self.total = 0;
+            self.floor.flashes = 0;
+            self.finished = false;
+            self.stalled = false;
+        }
+        while self.step < target && !self.finished {
+            self.advance();
+        }
+    }
+
+    fn frame(&self) -> Duration {
+        Duration::from_millis(1000 / self.fps as u64)
+    }
+
+    fn draw<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        // Raw mode doesn't return the carriage at a newline
+        let grid = format!("{}", self.floor).replace("\n", "\r\n");
+        write!(out, "{}{}{}", clear::All, cursor::Goto(1, 1), grid)?;
+        let state = if self.stalled {
+            "unsettled"
+        } else if self.finished {
+            if self.steps.is_none() { "synchronised" } else { "finished" }
+        } else if self.playing {
+            "playing"
+        } else {
+            "paused"
+        };
+        write!(out, "{}Step {} | {} this step: {} | total: {} | {} fps | {}{}\r\n",
+               style::Invert, self.step, self.rule.event(), self.floor.flashes, self.total, self.fps, state, style::Reset)?;
+        match self.jump {
+            Some(ref digits) => write!(out, "Jump to step: {}", digits)?,
+            None => write!(out, "space play/pause  n step  +/- speed  g jump  r restart  q quit")?
+        }
+        out.flush()
+    }
+
+    fn play(&mut self) -> io::Result<()> {
+        let stdout = io::stdout().into_raw_mode()?;
+        let mut screen = AlternateScreen::from(stdout);
+        write!(screen, "{}", cursor::Hide)?;
+        let mut keys = termion::async_stdin().keys();
+        let mut last = Instant::now();
+        self.draw(&mut screen)?;
+        loop {
+            let mut dirty = false;
+            for key in &mut keys {
+                dirty = true;
+                match (key?, self.jump.take()) {
+                    (Key::Char('\n'), Some(digits)) => {
+                        if let Ok(target) = digits.parse::<usize>() {
+                            self.seek(target);
+                        }
+                    },
+                    (Key::Char(c), Some(mut digits)) if c.is_ascii_digit() => {
+                        digits.push(c);
+                        self.jump = Some(digits);
+                    },
+                    (Key::Backspace, Some(mut digits)) => {
+                        digits.pop();
+                        self.jump = Some(digits);
+                    },
+                    (Key::Esc, Some(_)) => {},
+                    (_, Some(digits)) => self.jump = Some(digits),
+                    (Key::Char('q'), None) | (Key::Esc, None) | (Key::Ctrl('c'), None) => {
+                        write!(screen, "{}", cursor::Show)?;
+                        return Ok(());
+                    },
+                    (Key::Char(' '), None) => self.playing = !self.playing && !self.finished,
+                    (Key::Char('n'), None) => {
+                        self.playing = false;
+                        self.advance();
+                    },
+                    (Key::Char('+'), None) => self.fps = (self.fps * 2).min(240),
+                    (Key::Char('-'), None) => self.fps = (self.fps / 2).max(1),
+                    (Key::Char('g'), None) => {
+                        self.playing = false;
+                        self.jump = Some(String::new());
+                    },
+                    (Key::Char('r'), None) => self.seek(0),
+                    _ => {}
+                }
+            }
+            if self.playing && last.elapsed() >= self.frame() {
+                last = Instant::now();
+                self.advance();
+                dirty = true;
+            }
+            if dirty {
+                self.draw(&mut screen)?;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -134,27 +1000,155 @@ fn main() {
         return;
     }
 
-    println!("Reading {:?}", &args[1]);
-    if let Ok(lines) = read_lines(&args[1]) {
+    let mut filename: Option<&String> = None;
+    let mut rule: &str = "octopus";
+    let mut options = Options {
+        steps: None,
+        max_steps: 1_000_000,
+        heatmap: false,
+        csv: None,
+        cells: None,
+        cast: None,
+        fps: 10,
+        quiet: false
+    };
+    let mut boundary = Boundary::Clip;
+    let mut comparing = false;
+    let mut threads: usize = 1;
+    let mut play = false;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--rule" => {
+                i += 1;
+                rule = args.get(i).map_or("", |r| r.as_str());
+            },
+            "--steps" => {
+                i += 1;
+                match args.get(i).map(|v| v.parse::<usize>()) {
+                    Some(Ok(n)) => options.steps = Some(n),
+                    _ => {
+                        println!("Invalid value for --steps");
+                        return;
+                    }
+                }
+            },
+            "--max-steps" => {
+                i += 1;
+                match args.get(i).map(|v| v.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => options.max_steps = n,
+                    _ => {
+                        println!("Invalid value for --max-steps");
+                        return;
+                    }
+                }
+            },
+            "--boundary" => {
+                i += 1;
+                match Boundary::parse(args.get(i).map_or("", |b| b.as_str())) {
+                    Ok(b) => boundary = b,
+                    Err(e) => {
+                        println!("{}", e);
+                        return;
+                    }
+                }
+            },
+            "--compare" => comparing = true,
+            "--threads" => {
+                i += 1;
+                match args.get(i).map(|v| v.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => threads = n,
+                    _ => {
+                        println!("Invalid value for --threads");
+                        return;
+                    }
+                }
+            },
+            "--quiet" => options.quiet = true,
+            "--cast" => {
+                i += 1;
+                options.cast = args.get(i).cloned();
+            },
+            "--heatmap" => options.heatmap = true,
+            "--csv" => {
+                i += 1;
+                options.csv = args.get(i).cloned();
+            },
+            "--cells" => {
+                i += 1;
+                options.cells = args.get(i).cloned();
+            },
+            "--play" => play = true,
+            "--fps" => {
+                i += 1;
+                match args.get(i).map(|v| v.parse::<u32>()) {
+                    Some(Ok(n)) if n > 0 => options.fps = n,
+                    _ => {
+                        println!("Invalid value for --fps");
+                        return;
+                    }
+                }
+            },
+            _ => filename = Some(&args[i])
+        }
+        i += 1;
+    }
+    let filename = match filename {
+        Some(f) => f,
+        None => {
+            println!("No input file specified");
+            return;
+        }
+    };
+
+    println!("Reading {:?}", filename);
+    if let Ok(lines) = read_lines(filename) {
         let mut floor = Floor::new();
-        for line in lines {
+        let lines: Vec<io::Result<String>> = lines.collect();
+        floor.integers = Floor::separated(&lines);
+        for (n, line) in lines.into_iter().enumerate() {
             if let Ok(strval) = line {
-                floor.add(&strval);
+                if let Err(e) = floor.add(&strval) {
+                    println!("Line {}: {}", n + 1, e);
+                    return;
+                }
             }
         }
         floor.convert();
-        let mut steps = 0;
-        loop {
-            floor.energise();
-            floor.flashes = 0;
-            while floor.flash() {}
-            println!("Step {}:\n{}", steps, floor);
-            steps += 1;
-            if floor.flashes == floor.area {
-                break;
+        floor.boundary = boundary;
+        floor.threads = threads;
+        if comparing {
+            let mut boundaries = vec![Boundary::Clip, Boundary::Wrap, Boundary::Reflect];
+            if !boundaries.contains(&boundary) {
+                boundaries.push(boundary);
+            }
+            match rule {
+                "octopus" => compare(Octopus, &floor, &boundaries, options.max_steps),
+                "life" => compare(Life, &floor, &boundaries, options.max_steps),
+                "sandpile" => compare(Sandpile, &floor, &boundaries, options.max_steps),
+                _ => println!("Unknown rule {:?}, expected octopus, life or sandpile", rule)
+            }
+            return;
+        }
+        // Only the octopuses are known to synchronise, so other rules need a step count
+        if rule != "octopus" && options.steps.is_none() {
+            options.steps = Some(100);
+        }
+        let result = match (rule, play) {
+            ("octopus", true) => Player::new(Octopus, &floor, options.steps, options.fps).play(),
+            ("life", true) => Player::new(Life, &floor, options.steps, options.fps).play(),
+            ("sandpile", true) => Player::new(Sandpile, &floor, options.steps, options.fps).play(),
+            ("octopus", false) => run(Octopus, &floor, &options),
+            ("life", false) => run(Life, &floor, &options),
+            ("sandpile", false) => run(Sandpile, &floor, &options),
+            _ => {
+                println!("Unknown rule {:?}, expected octopus, life or sandpile", rule);
+                Ok(())
             }
+        };
+        if let Err(e) = result {
+            println!("Error: {}", e);
         }
-        println!("Synchronised flash after {} steps", steps);
     }
 }
 
@@ -0,0 +1,74 @@
+use std::env;
+use std::io::{self, Write};
+
+// Small xorshift generator so the output is reproducible from a seed
+struct Random {
+    state: u64
+}
+
+impl Random {
+    fn new(seed: u64) -> Random {
+        Random {
+            // A zero state would only ever produce zeros
+            state: if seed == 0 { 0x9e3779b97f4a7c15 } else { seed }
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    fn below(&mut self, max: usize) -> usize {
+        (self.next() % max as u64) as usize
+    }
+}
+
+fn parse(args: &[String], i: usize, name: &str) -> Option<usize> {
+    match args.get(i).map(|v| v.parse::<usize>()) {
+        Some(Ok(n)) if n > 0 => Some(n),
+        _ => {
+            println!("Invalid {} {:?}", name, args.get(i));
+            None
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() <= 3 {
+        println!("No parameters specified");
+        println!("Usage: {} <seed> <width> <height>", &args[0]);
+        return;
+    }
+
+    let seed: u64 = match args[1].parse() {
+        Ok(s) => s,
+        Err(_) => {
+            println!("Invalid seed {:?}", &args[1]);
+            return;
+        }
+    };
+    let (width, height) = match (parse(&args, 2, "width"), parse(&args, 3, "height")) {
+        (Some(w), Some(h)) => (w, h),
+        _ => return
+    };
+
+    // Rows of random energy levels, buffered since the grids can run to millions of cells
+    let mut random = Random::new(seed);
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    let mut row = vec![b'0'; width + 1];
+    row[width] = b'\n';
+    for _y in 0..height {
+        for c in row.iter_mut().take(width) {
+            *c = b'0' + random.below(10) as u8;
+        }
+        if out.write_all(&row).is_err() {
+            return;
+        }
+    }
+}
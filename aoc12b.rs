@@ -1,8 +1,8 @@
 use std::env;
 use std::fs::File;
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, Write};
 use std::path::Path;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 struct Tree {
     children: Vec<String>
@@ -20,28 +20,77 @@ impl Tree {
     }
 }
 
+// Which caves may be entered again, and where paths begin and end
+struct VisitPolicy {
+    // Most times any one small cave may be visited
+    max_visits: usize,
+    // How many small caves may be visited more than once
+    max_exceeding: usize,
+    // Caves that may only be visited once, whatever their size
+    never_revisit: Vec<String>,
+    start: String,
+    end: String
+}
+
+impl VisitPolicy {
+    // Part a: small caves are visited at most once
+    fn part_a() -> VisitPolicy {
+        VisitPolicy {
+            max_visits: 1,
+            max_exceeding: 0,
+            never_revisit: Vec::new(),
+            start: String::from("start"),
+            end: String::from("end")
+        }
+    }
+
+    // Part b: a single small cave may be visited twice
+    fn part_b() -> VisitPolicy {
+        VisitPolicy {
+            max_visits: 2,
+            max_exceeding: 1,
+            ..VisitPolicy::part_a()
+        }
+    }
+
+    fn small(id: &str) -> bool {
+        id == id.to_lowercase()
+    }
+
+    // The start is never revisited, otherwise paths would loop back through it
+    fn never(&self, id: &str) -> bool {
+        id == self.start || self.never_revisit.iter().any(|n| n == id)
+    }
+
+    // Given how often a cave has already been visited and how many caves already
+    // exceed one visit, returns the new exceeding count if it may be entered again
+    fn admit(&self, id: &str, visits: usize, exceeded: usize) -> Option<usize> {
+        if visits > 0 && self.never(id) {
+            return None;
+        }
+        if !VisitPolicy::small(id) {
+            return Some(exceeded);
+        }
+        if visits + 1 > self.max_visits {
+            None
+        } else if visits + 1 == 2 {
+            if exceeded < self.max_exceeding { Some(exceeded + 1) } else { None }
+        } else {
+            Some(exceeded)
+        }
+    }
+}
+
 struct Waypoint {
     path: String,
     id: String,
-    detour: bool
+    exceeded: usize
 }
 
 impl Waypoint {
-    fn noncycle_detour(&self) -> Option<bool> {
-        if self.id != self.id.to_lowercase() {
-            return Some(self.detour);
-        }
-        let mut detour: bool = self.detour;
-        for i in self.path.split("-") {
-            if self.id == i {
-                if detour {
-                    return None;
-                } else {
-                    detour = true;
-                }
-            }
-        }
-        return Some(detour);
+    fn admit(&self, policy: &VisitPolicy) -> Option<usize> {
+        let visits = self.path.split("-").filter(|i| *i == self.id).count();
+        policy.admit(&self.id, visits, self.exceeded)
     }
 }
 
@@ -78,32 +127,200 @@ impl Graph {
         }
     }
 
-    fn dfs_visit(&self, f: impl Fn(&str)) -> usize{
+    // Caves reachable from a cave without passing through the barrier cave
+    fn reachable<'a>(&'a self, from: &'a str, barrier: &str) -> BTreeSet<&'a str> {
+        let mut seen: BTreeSet<&str> = BTreeSet::new();
+        let mut stack: Vec<&str> = vec![from];
+        while let Some(id) = stack.pop() {
+            if !seen.insert(id) || id == barrier {
+                continue;
+            }
+            if let Some(t) = self.index.get(id) {
+                stack.extend(t.children.iter().map(|c| c.as_str()));
+            }
+        }
+        seen
+    }
+
+    // Two caves that may both be revisited without limit can be bounced between
+    // forever, so if a path can reach such a link and still get to the end there
+    // are infinitely many paths. Links cut off from the end are pruned by the
+    // traversals instead, as they can never finish a path
+    fn check(&self, policy: &VisitPolicy) -> Result<(), String> {
+        // Entering the end finishes a path and the start is never re-entered, so neither can loop
+        let unlimited = |id: &str| !VisitPolicy::small(id) && !policy.never(id) && id != policy.end && id != policy.start;
+        let from_start = self.reachable(&policy.start, &policy.end);
+        let to_end = self.reachable(&policy.end, &policy.start);
+        for (id, t) in &self.index {
+            if !unlimited(id) || !from_start.contains(id.as_str()) || !to_end.contains(id.as_str()) {
+                continue;
+            }
+            if let Some(child) = t.children.iter().find(|c| unlimited(c)) {
+                if child == id {
+                    return Err(format!("infinitely many paths, big cave {} is linked to itself", id));
+                }
+                return Err(format!("infinitely many paths, big caves {} and {} are linked", id, child));
+            }
+        }
+        Ok(())
+    }
+
+    // Calls f with each path found, stopping early once it returns false
+    fn dfs_visit(&self, policy: &VisitPolicy, mut f: impl FnMut(&str) -> bool) -> Result<usize, String> {
+        self.check(policy)?;
+        // Caves cut off from the end can't finish a path, and may loop among big caves forever
+        let leads = self.reachable(&policy.end, &policy.start);
         let mut stack: Vec<Waypoint> = Vec::new();
         let mut count: usize = 0;
 
-        stack.push(Waypoint { path: String::from(""), id: String::from("start"), detour: false});
+        stack.push(Waypoint { path: String::from(""), id: String::from(&policy.start[..]), exceeded: 0});
 
         while stack.len() > 0 {
             if let Some(w) = stack.pop() {
                 let new_path = String::from(&w.path) + "-" + &w.id;
-                if w.id == "end" {
-                    (f)(&new_path);
+                if w.id == policy.end {
                     count += 1;
+                    if !(f)(&new_path) {
+                        break;
+                    }
                     continue;
                 }
-                if w.id == "start" && w.path != "" {
-                    continue;
-                }
-                if let Some(d) = w.noncycle_detour() {
+                if let Some(e) = w.admit(policy) {
                     if let Some(t) = self.index.get(&w.id) {
-                        for child in &t.children {
-                            stack.push(Waypoint { path: String::from(&new_path), id: String::from(child), detour: d });
+                        for child in t.children.iter().filter(|c| leads.contains(c.as_str())) {
+                            stack.push(Waypoint { path: String::from(&new_path), id: String::from(child), exceeded: e });
                         }
                     }
                 }
             }
         }
+        Ok(count)
+    }
+
+    // GraphViz source for the caves, with big caves as filled boxes, small caves as
+    // ellipses, the start and end marked out, and each given path drawn over the
+    // links in its own colour
+    fn to_dot(&self, policy: &VisitPolicy, paths: &[Vec<String>]) -> String {
+        const PALETTE: [&str; 8] = ["red", "blue", "forestgreen", "darkorange", "purple", "deeppink", "teal", "goldenrod"];
+        let mut result = String::from("graph caves {\n    node [fontname=\"Helvetica\"];\n");
+        for id in self.index.keys() {
+            let style = if *id == policy.start {
+                "shape=doublecircle, style=filled, fillcolor=palegreen"
+            } else if *id == policy.end {
+                "shape=doublecircle, style=filled, fillcolor=salmon"
+            } else if VisitPolicy::small(id) {
+                "shape=ellipse"
+            } else {
+                "shape=box, style=filled, fillcolor=lightblue"
+            };
+            result += &format!("    {:?} [{}];\n", id, style);
+        }
+        // Each link is stored both ways round but drawn once
+        for (id, t) in &self.index {
+            for child in t.children.iter().filter(|c| id <= *c) {
+                result += &format!("    {:?} -- {:?} [color=gray];\n", id, child);
+            }
+        }
+        for (n, path) in paths.iter().enumerate() {
+            let colour = PALETTE[n % PALETTE.len()];
+            for pair in path.windows(2) {
+                result += &format!("    {:?} -- {:?} [color={}, penwidth=2, label=\"{}\", fontcolor={}];\n",
+                                   pair[0], pair[1], colour, n + 1, colour);
+            }
+        }
+        result += "}\n";
+        result
+    }
+}
+
+// Counts paths without building them, by interning the cave names to ids and
+// memoising on the cave, the visit counts of the limited caves packed into a
+// u128 and how many caves have exceeded one visit
+struct Counter<'a> {
+    policy: &'a VisitPolicy,
+    names: Vec<String>,
+    links: Vec<Vec<usize>>,
+    // Offset of each limited cave's count in the packed counts, None for unlimited caves
+    slots: Vec<Option<u32>>,
+    // Bits per packed count
+    width: u32,
+    start: usize,
+    end: usize,
+    memo: HashMap<(usize, u128, usize), u64>
+}
+
+impl<'a> Counter<'a> {
+    fn new(graph: &Graph, policy: &'a VisitPolicy) -> Result<Counter<'a>, String> {
+        let ids: BTreeMap<&str, usize> = graph.index.keys().enumerate().map(|(i, k)| (k.as_str(), i)).collect();
+        let width = 64 - (policy.max_visits as u64).leading_zeros();
+        let mut slots: Vec<Option<u32>> = Vec::new();
+        let mut offset = 0;
+        for name in graph.index.keys() {
+            if VisitPolicy::small(name) || policy.never(name) {
+                if offset + width > 128 {
+                    return Err(format!("too many small caves to count {} visits each", policy.max_visits));
+                }
+                slots.push(Some(offset));
+                offset += width;
+            } else {
+                slots.push(None);
+            }
+        }
+        let find = |name: &str| ids.get(name).cloned().ok_or(format!("no {:?} cave", name));
+        graph.check(policy)?;
+        // Links into caves cut off from the end are dropped, so the count never
+        // wanders into a part of the graph it can't finish from
+        let leads = graph.reachable(&policy.end, &policy.start);
+        Ok(Counter {
+            policy,
+            names: graph.index.keys().cloned().collect(),
+            links: graph.index.values().map(|t| {
+                t.children.iter().filter(|c| leads.contains(c.as_str())).map(|c| ids[c.as_str()]).collect()
+            }).collect(),
+            slots,
+            width,
+            start: find(&policy.start)?,
+            end: find(&policy.end)?,
+            memo: HashMap::new()
+        })
+    }
+
+    fn count(&mut self) -> u64 {
+        let start = self.start;
+        match self.enter(start, 0, 0) {
+            Some((counts, exceeded)) => self.paths(start, counts, exceeded),
+            None => 0
+        }
+    }
+
+    // The packed counts and exceeding count after entering a cave, if the policy allows it
+    fn enter(&self, id: usize, counts: u128, exceeded: usize) -> Option<(u128, usize)> {
+        let offset = match self.slots[id] {
+            Some(o) => o,
+            None => return Some((counts, exceeded))
+        };
+        let mask = (1u128 << self.width) - 1;
+        let visits = ((counts >> offset) & mask) as usize;
+        let exceeded = self.policy.admit(&self.names[id], visits, exceeded)?;
+        // The policy turns a cave away before its count outgrows its slot
+        Some((counts + (1 << offset), exceeded))
+    }
+
+    fn paths(&mut self, id: usize, counts: u128, exceeded: usize) -> u64 {
+        if id == self.end {
+            return 1;
+        }
+        if let Some(count) = self.memo.get(&(id, counts, exceeded)) {
+            return *count;
+        }
+        let mut count = 0;
+        for i in 0..self.links[id].len() {
+            let child = self.links[id][i];
+            if let Some((c, e)) = self.enter(child, counts, exceeded) {
+                count += self.paths(child, c, e);
+            }
+        }
+        self.memo.insert((id, counts, exceeded), count);
         count
     }
 }
@@ -116,9 +333,83 @@ fn main() {
         return;
     }
 
+    let mut filename: Option<&String> = None;
+    let mut counting = false;
+    let mut dot: Option<&String> = None;
+    let mut overlay: usize = 0;
+    let mut policy = VisitPolicy::part_b();
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--count" => counting = true,
+            "--dot" => {
+                i += 1;
+                dot = args.get(i);
+            },
+            "--overlay" => {
+                i += 1;
+                match args.get(i).map(|v| v.parse::<usize>()) {
+                    Some(Ok(n)) => overlay = n,
+                    _ => {
+                        println!("Invalid value for --overlay");
+                        return;
+                    }
+                }
+            },
+            "--part" => {
+                i += 1;
+                match args.get(i).map(|p| p.as_str()) {
+                    Some("a") => policy = VisitPolicy::part_a(),
+                    Some("b") => policy = VisitPolicy::part_b(),
+                    _ => {
+                        println!("Invalid value for --part, expected a or b");
+                        return;
+                    }
+                }
+            },
+            "--max-visits" | "--max-exceeding" => {
+                let flag = &args[i];
+                i += 1;
+                match args.get(i).map(|v| v.parse::<usize>()) {
+                    Some(Ok(n)) if flag == "--max-visits" && n > 0 => policy.max_visits = n,
+                    Some(Ok(n)) if flag == "--max-exceeding" => policy.max_exceeding = n,
+                    _ => {
+                        println!("Invalid value for {}", flag);
+                        return;
+                    }
+                }
+            },
+            "--never" | "--start" | "--end" => {
+                let flag = &args[i];
+                i += 1;
+                let name = match args.get(i) {
+                    Some(n) => n.clone(),
+                    None => {
+                        println!("Missing cave name for {}", flag);
+                        return;
+                    }
+                };
+                match flag.as_str() {
+                    "--never" => policy.never_revisit.push(name),
+                    "--start" => policy.start = name,
+                    _ => policy.end = name
+                }
+            },
+            _ => filename = Some(&args[i])
+        }
+        i += 1;
+    }
+    let filename = match filename {
+        Some(f) => f,
+        None => {
+            println!("No input file specified");
+            return;
+        }
+    };
+
     let mut graph = Graph::new();
-    println!("Reading {:?}", &args[1]);
-    if let Ok(lines) = read_lines(&args[1]) {
+    println!("Reading {:?}", filename);
+    if let Ok(lines) = read_lines(filename) {
         for line in lines {
             if let Ok(strval) = line {
                 println!("Parsing {}", strval);
@@ -126,8 +417,38 @@ fn main() {
             }
         }
     }
-    let count = graph.dfs_visit(|s| println!("Item: {}", s));
-    println!("Count: {}", count)
+    if let Some(path) = dot {
+        // The overlay is the first paths found, which can't be listed if there are infinitely many
+        let mut paths: Vec<Vec<String>> = Vec::new();
+        if overlay > 0 {
+            let found = graph.dfs_visit(&policy, |s| {
+                paths.push(s.split("-").filter(|c| !c.is_empty()).map(String::from).collect());
+                paths.len() < overlay
+            });
+            if let Err(e) = found {
+                println!("No paths to overlay: {}", e);
+            }
+        }
+        match File::create(path).and_then(|mut f| f.write_all(graph.to_dot(&policy, &paths).as_bytes())) {
+            Ok(_) => println!("Wrote {} with {} path(s)", path, paths.len()),
+            Err(e) => println!("Can't write {}: {}", path, e)
+        }
+        return;
+    }
+    if counting {
+        match Counter::new(&graph, &policy) {
+            Ok(mut counter) => println!("Count: {}", counter.count()),
+            Err(e) => println!("Can't count paths: {}", e)
+        }
+        return;
+    }
+    match graph.dfs_visit(&policy, |s| {
+        println!("Item: {}", s);
+        true
+    }) {
+        Ok(count) => println!("Count: {}", count),
+        Err(e) => println!("Can't list paths: {}", e)
+    }
 }
 
 // The output is wrapped in a Result to allow matching on errors
@@ -2,6 +2,209 @@ use std::env;
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet};
+
+struct Frequency {
+    freq: BTreeMap<char, u32>
+}
+
+impl Frequency {
+    fn new() -> Frequency {
+        Frequency{
+            freq: BTreeMap::new()
+        }
+    }
+
+    fn add(&mut self, value: &str) {
+        for c in value.chars() {
+            if let Some(f) = self.freq.get_mut(&c) {
+                *f += 1;
+            } else {
+                self.freq.insert(c, 1);
+            }
+        }
+    }
+
+    fn extract_char(&self, frequency: u32) -> Option<char> {
+        for (k,v) in &self.freq {
+            if v == &frequency {
+                return Some(*k);
+            }
+        }
+        None
+    }
+}
+
+struct Length {
+    length: BTreeMap<usize, Vec<String>>
+}
+
+impl Length {
+    fn new() -> Length {
+        Length {
+            length: BTreeMap::new()
+        }
+    }
+
+    fn add(&mut self, value: &str) {
+        let len = value.len();
+        if let Some(l) = self.length.get_mut(&len) {
+            (*l).push(String::from(value));
+        } else {
+            self.length.insert(len, vec![String::from(value)]);
+        }
+    }
+
+    fn extract_char(&self, mapping:&BTreeMap<char, char>, len: usize) -> Option<char> {
+        if let Some(strs) = self.length.get(&len) {
+            for str in strs {
+                let result:Vec<char> = str.chars().filter(|x| !mapping.contains_key(x)).collect();
+                if result.len() == 1 {
+                    return Some(result[0]);
+                }
+            }
+        }
+        None
+    }
+}
+
+struct Parser {
+    freq: Frequency,
+    length: Length,
+    seen: BTreeSet<String>,
+    mapping: BTreeMap<char, char>,
+    // Every wiring consistent with the patterns, when they had to be searched for
+    candidates: Vec<BTreeMap<char, char>>
+}
+
+impl Parser {
+    fn new() -> Parser {
+        Parser {
+            freq: Frequency::new(),
+            length: Length::new(),
+            seen: BTreeSet::new(),
+            mapping: BTreeMap::new(),
+            candidates: Vec::new()
+        }
+    }
+
+    // Ignores repeated patterns so outputs can be fed in alongside the inputs, and
+    // malformed ones so a bad token can't stop the rest of the line decoding
+    fn add(&mut self, value: &str) {
+        let mut sorted: Vec<char> = value.chars().collect();
+        sorted.sort();
+        sorted.dedup();
+        if sorted.len() != value.len() || sorted.len() < 2 || sorted.iter().any(|c| *c < 'a' || *c > 'g') {
+            return;
+        }
+        let key: String = sorted.into_iter().collect();
+        if self.seen.insert(key) {
+            self.freq.add(value);
+            self.length.add(value);
+        }
+    }
+
+    fn extract(&mut self, numbers: &Numbers) {
+        // The segment frequencies only identify wires when all ten digits were seen
+        if self.seen.len() != 10 {
+            self.search(numbers);
+            return;
+        }
+        if let Some(b) = self.freq.extract_char(6) {
+            self.mapping.insert(b, 'b');
+        }
+        if let Some(e) = self.freq.extract_char(4) {
+            self.mapping.insert(e, 'e');
+        }
+        if let Some(f) = self.freq.extract_char(9) {
+            self.mapping.insert(f, 'f');
+        }
+        if let Some(c) = self.length.extract_char(&self.mapping, 2) {
+            self.mapping.insert(c, 'c');
+        }
+        if let Some(a) = self.length.extract_char(&self.mapping, 3) {
+            self.mapping.insert(a, 'a');
+        }
+        if let Some(d) = self.length.extract_char(&self.mapping, 4) {
+            self.mapping.insert(d, 'd');
+        }
+        if let Some(g) = self.length.extract_char(&self.mapping, 7) {
+            self.mapping.insert(g, 'g');
+        }
+    }
+
+    // Tries every wiring, keeping those under which all the patterns seen decode to digits
+    fn search(&mut self, numbers: &Numbers) {
+        let mut wires: Vec<char> = "abcdefg".chars().collect();
+        let patterns: Vec<&String> = self.length.length.values().flatten().collect();
+        let mut found: Vec<BTreeMap<char, char>> = Vec::new();
+        Parser::permute(numbers, &patterns, &mut wires, 0, &mut found);
+        if let Some(mapping) = found.first() {
+            self.mapping = mapping.clone();
+        }
+        self.candidates = found;
+    }
+
+    fn permute(numbers: &Numbers, patterns: &[&String], wires: &mut [char], k: usize, found: &mut Vec<BTreeMap<char, char>>) {
+        if k == wires.len() {
+            let mapping: BTreeMap<char, char> = wires.iter().cloned().zip("abcdefg".chars()).collect();
+            if patterns.iter().all(|p| numbers.convert_digit(&mapping, p).is_some()) {
+                found.push(mapping);
+            }
+            return;
+        }
+        for i in k..wires.len() {
+            wires.swap(k, i);
+            Parser::permute(numbers, patterns, wires, k + 1, found);
+            wires.swap(k, i);
+        }
+    }
+
+    // Too few patterns can fit several wirings; that only matters if they read the outputs differently
+    fn ambiguous(&self, numbers: &Numbers, outputs: &[&str]) -> bool {
+        let read = |mapping: &BTreeMap<char, char>| -> Vec<Option<usize>> {
+            outputs.iter().map(|o| numbers.convert_digit(mapping, o)).collect()
+        };
+        match self.candidates.split_first() {
+            Some((first, rest)) => rest.iter().any(|m| read(m) != read(first)),
+            None => false
+        }
+    }
+}
+
+struct Numbers {
+    numbers: BTreeMap<&'static str, &'static str>
+}
+
+impl Numbers {
+    fn new() -> Numbers {
+        let mut numbers:BTreeMap<&'static str, &'static str> = BTreeMap::new();
+        numbers.insert("abcefg", "0");
+        numbers.insert("cf", "1");
+        numbers.insert("acdeg", "2");
+        numbers.insert("acdfg", "3");
+        numbers.insert("bcdf", "4");
+        numbers.insert("abdfg", "5");
+        numbers.insert("abdefg", "6");
+        numbers.insert("acf", "7");
+        numbers.insert("abcdefg", "8");
+        numbers.insert("abcdfg", "9");
+
+        Numbers {
+            numbers
+        }
+    }
+
+    fn convert_digit(&self, mapping:&BTreeMap<char, char>, output: &str) -> Option<usize> {
+        let mut result:Vec<char> = Vec::new();
+        for c in output.chars() {
+            result.push(*mapping.get(&c)?);
+        }
+        result.sort();
+        let new_o:String = result.into_iter().collect();
+        self.numbers.get(new_o.as_str()).map(|n| n.parse().unwrap())
+    }
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -13,20 +216,59 @@ fn main() {
 
     println!("Reading {:?}", &args[1]);
     if let Ok(lines) = read_lines(&args[1]) {
-        let mut unique = 0;
+        let numbers = Numbers::new();
+
+        let mut histogram: Vec<usize> = vec![0; 10];
+        let mut undecoded = 0;
+        let mut ambiguous = 0;
         for line in lines {
             if let Ok(strval) = line {
-                let entries: Vec<&str> = strval.split(' ').collect();
-                for value in &entries[11..] {
-                    unique += match value.len() {
-                        2|3|4|7=>1,
-                        _=>0
-                    };
+                let (patterns, outputs) = parse_line(&strval);
+                let mut parser = Parser::new();
+                for value in patterns.iter().chain(outputs.iter()) {
+                    parser.add(value);
+                }
+                parser.extract(&numbers);
+                if parser.ambiguous(&numbers, &outputs) {
+                    ambiguous += outputs.len();
+                    continue;
+                }
+                for o in outputs {
+                    match numbers.convert_digit(&parser.mapping, o) {
+                        Some(n) => histogram[n] += 1,
+                        None => undecoded += 1
+                    }
                 }
             }
         }
-        println!("Unique: {}", unique);
+        for (digit, count) in histogram.iter().enumerate() {
+            println!("{}: {}", digit, count);
+        }
+        if undecoded > 0 {
+            println!("Undecoded: {}", undecoded);
+        }
+        if ambiguous > 0 {
+            println!("Ambiguous: {}", ambiguous);
+        }
+        println!("Unique: {}", histogram[1] + histogram[4] + histogram[7] + histogram[8]);
+    }
+}
+
+// Splits a line into the patterns before the '|' and the outputs after it
+fn parse_line(line: &str) -> (Vec<&str>, Vec<&str>) {
+    let mut patterns: Vec<&str> = Vec::new();
+    let mut outputs: Vec<&str> = Vec::new();
+    let mut input: bool = true;
+    for value in line.split_whitespace() {
+        if value == "|" {
+            input = false;
+        } else if input {
+            patterns.push(value);
+        } else {
+            outputs.push(value);
+        }
     }
+    (patterns, outputs)
 }
 
 // The output is wrapped in a Result to allow matching on errors
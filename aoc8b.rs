@@ -1,8 +1,12 @@
+extern crate termion;
+
+use termion::color;
+
 use std::env;
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 struct Frequency {
     freq: BTreeMap<char, u32>
@@ -71,7 +75,10 @@ impl Length {
 struct Parser {
     freq: Frequency,
     length: Length,
-    mapping: BTreeMap<char, char>
+    seen: BTreeSet<String>,
+    mapping: BTreeMap<char, char>,
+    // Every wiring consistent with the patterns, when they had to be searched for
+    candidates: Vec<BTreeMap<char, char>>
 }
 
 impl Parser {
@@ -79,16 +86,34 @@ impl Parser {
         Parser {
             freq: Frequency::new(),
             length: Length::new(),
-            mapping: BTreeMap::new()
+            seen: BTreeSet::new(),
+            mapping: BTreeMap::new(),
+            candidates: Vec::new()
         }
     }
 
+    // Ignores repeated patterns so outputs can be fed in alongside the inputs, and
+    // malformed ones so a bad token can't stop the rest of the line decoding
     fn add(&mut self, value: &str) {
-        self.freq.add(value);
-        self.length.add(value);
+        let mut sorted: Vec<char> = value.chars().collect();
+        sorted.sort();
+        sorted.dedup();
+        if sorted.len() != value.len() || sorted.len() < 2 || sorted.iter().any(|c| *c < 'a' || *c > 'g') {
+            return;
+        }
+        let key: String = sorted.into_iter().collect();
+        if self.seen.insert(key) {
+            self.freq.add(value);
+            self.length.add(value);
+        }
     }
 
-    fn extract(&mut self) {
+    fn extract(&mut self, numbers: &Numbers) {
+        // The segment frequencies only identify wires when all ten digits were seen
+        if self.seen.len() != 10 {
+            self.search(numbers);
+            return;
+        }
         if let Some(b) = self.freq.extract_char(6) {
             self.mapping.insert(b, 'b');
         }
@@ -111,6 +136,44 @@ impl Parser {
             self.mapping.insert(g, 'g');
         }
     }
+
+    // Tries every wiring, keeping those under which all the patterns seen decode to digits
+    fn search(&mut self, numbers: &Numbers) {
+        let mut wires: Vec<char> = "abcdefg".chars().collect();
+        let patterns: Vec<&String> = self.length.length.values().flatten().collect();
+        let mut found: Vec<BTreeMap<char, char>> = Vec::new();
+        Parser::permute(numbers, &patterns, &mut wires, 0, &mut found);
+        if let Some(mapping) = found.first() {
+            self.mapping = mapping.clone();
+        }
+        self.candidates = found;
+    }
+
+    fn permute(numbers: &Numbers, patterns: &[&String], wires: &mut [char], k: usize, found: &mut Vec<BTreeMap<char, char>>) {
+        if k == wires.len() {
+            let mapping: BTreeMap<char, char> = wires.iter().cloned().zip("abcdefg".chars()).collect();
+            if patterns.iter().all(|p| numbers.convert_digit(&mapping, p).is_some()) {
+                found.push(mapping);
+            }
+            return;
+        }
+        for i in k..wires.len() {
+            wires.swap(k, i);
+            Parser::permute(numbers, patterns, wires, k + 1, found);
+            wires.swap(k, i);
+        }
+    }
+
+    // Too few patterns can fit several wirings; that only matters if they read the outputs differently
+    fn ambiguous(&self, numbers: &Numbers, outputs: &[&str]) -> bool {
+        let read = |mapping: &BTreeMap<char, char>| -> Vec<Option<usize>> {
+            outputs.iter().map(|o| numbers.convert_digit(mapping, o)).collect()
+        };
+        match self.candidates.split_first() {
+            Some((first, rest)) => rest.iter().any(|m| read(m) != read(first)),
+            None => false
+        }
+    }
 }
 
 struct Numbers {
@@ -136,17 +199,83 @@ impl Numbers {
         }
     }
 
-    fn convert_num(&self, mapping:&BTreeMap<char, char>, outputs:&Vec<&str>) -> usize {
-        let mut num = String::from("");
+    fn convert_digit(&self, mapping:&BTreeMap<char, char>, output: &str) -> Option<usize> {
+        let mut result:Vec<char> = Vec::new();
+        for c in output.chars() {
+            result.push(*mapping.get(&c)?);
+        }
+        result.sort();
+        let new_o:String = result.into_iter().collect();
+        self.numbers.get(new_o.as_str()).map(|n| n.parse().unwrap())
+    }
+
+    fn convert_num(&self, mapping:&BTreeMap<char, char>, outputs:&[&str]) -> Option<usize> {
+        let mut num = 0;
+        for o in outputs {
+            num = num * 10 + self.convert_digit(mapping, o)?;
+        }
+        Some(num)
+    }
+}
+
+// Draws digits as three rows of a seven-segment display
+struct Renderer {
+    colour: bool,
+    wiring: bool
+}
+
+impl Renderer {
+    fn new(colour: bool, wiring: bool) -> Renderer {
+        Renderer {
+            colour,
+            wiring
+        }
+    }
+
+    // Returns the segment drawn at each of the nine cells of a digit
+    fn cells(segments: &[char]) -> Vec<char> {
+        let lit = |s: char, ch: char| if segments.contains(&s) { ch } else { ' ' };
+        vec![' ', lit('a', '_'), ' ',
+             lit('b', '|'), lit('d', '_'), lit('c', '|'),
+             lit('e', '|'), lit('g', '_'), lit('f', '|')]
+    }
+
+    fn paint(&self, rows: &mut [String], segments: &[char], fg: &dyn color::Color) {
+        let cells = Renderer::cells(segments);
+        for row in 0..3 {
+            if self.colour {
+                rows[row].push_str(&format!("{}", color::Fg(fg)));
+            }
+            rows[row].extend(&cells[row * 3..row * 3 + 3]);
+            if self.colour {
+                rows[row].push_str(&format!("{}", color::Fg(color::Reset)));
+            }
+            rows[row].push(' ');
+        }
+    }
+
+    fn render(&self, numbers: &Numbers, mapping: &BTreeMap<char, char>, outputs: &Vec<&str>) -> String {
+        let mut rows: Vec<String> = vec![String::from(""), String::from(""), String::from("")];
         for o in outputs {
-            let mut result:Vec<&char> = o.chars().map(|c| &mapping[&c]).collect();
-            result.sort();
-            let new_o:String = result.into_iter().collect();
-            if let Some(n) = self.numbers.get(new_o.as_str()) {
-                num.push_str(n);
+            // Unmapped wires are left dark so a broken mapping shows up as a missing segment
+            let mut segments: Vec<char> = o.chars().filter_map(|c| mapping.get(&c).cloned()).collect();
+            segments.sort();
+            let valid = segments.len() == o.len() && numbers.numbers.contains_key(segments.iter().collect::<String>().as_str());
+            if valid {
+                self.paint(&mut rows, &segments, &color::Green);
+            } else {
+                self.paint(&mut rows, &segments, &color::Red);
             }
         }
-        return num.parse().unwrap();
+        if self.wiring {
+            for row in rows.iter_mut() {
+                row.push_str("  ");
+            }
+            for o in outputs {
+                self.paint(&mut rows, &o.chars().collect::<Vec<char>>(), &color::Yellow);
+            }
+        }
+        rows.join("\n")
     }
 }
 
@@ -158,35 +287,85 @@ fn main() {
         return;
     }
 
-    println!("Reading {:?}", &args[1]);
-    if let Ok(lines) = read_lines(&args[1]) {
+    let mut filename: Option<&String> = None;
+    let mut display: bool = false;
+    let mut wiring: bool = false;
+    let mut colour: bool = false;
+    for arg in &args[1..] {
+        match arg.as_str() {
+            "--display" => display = true,
+            "--wiring" => {
+                display = true;
+                wiring = true;
+            },
+            "--colour" => colour = true,
+            _ => filename = Some(arg)
+        }
+    }
+    let filename = match filename {
+        Some(f) => f,
+        None => {
+            println!("No input file specified");
+            return;
+        }
+    };
+
+    println!("Reading {:?}", filename);
+    if let Ok(lines) = read_lines(filename) {
         let numbers = Numbers::new();
+        let renderer = Renderer::new(colour, wiring);
 
         let mut total = 0;
-        for line in lines {
+        let mut skipped = 0;
+        for (n, line) in lines.enumerate() {
             if let Ok(strval) = line {
+                let (patterns, outputs) = parse_line(&strval);
                 let mut parser = Parser::new();
-
-                let entries: Vec<&str> = strval.split(' ').collect();
-                let mut input:bool = true;
-                let mut outputs:Vec<&str> = Vec::new();
-                for value in entries {
-                    if value == "|" {
-                        input = false;
-                    } else if input {
-                        parser.add(value);
-                    } else {
-                        outputs.push(value);
+                for value in patterns.iter().chain(outputs.iter()) {
+                    parser.add(value);
+                }
+                parser.extract(&numbers);
+                if display {
+                    println!("{}\n{}\n", strval, renderer.render(&numbers, &parser.mapping, &outputs));
+                }
+                if parser.ambiguous(&numbers, &outputs) {
+                    println!("Line {}: ambiguous, {} wirings fit but disagree on the outputs", n + 1, parser.candidates.len());
+                    skipped += 1;
+                    continue;
+                }
+                match numbers.convert_num(&parser.mapping, &outputs) {
+                    Some(num) => total += num,
+                    None => {
+                        println!("Line {}: can't decode outputs {}", n + 1, outputs.join(" "));
+                        skipped += 1;
                     }
                 }
-                parser.extract();
-                total += numbers.convert_num(&parser.mapping, &outputs);
             }
         }
+        if skipped > 0 {
+            println!("Skipped: {} lines", skipped);
+        }
         println!("Total: {}", total);
     }
 }
 
+// Splits a line into the patterns before the '|' and the outputs after it
+fn parse_line(line: &str) -> (Vec<&str>, Vec<&str>) {
+    let mut patterns: Vec<&str> = Vec::new();
+    let mut outputs: Vec<&str> = Vec::new();
+    let mut input: bool = true;
+    for value in line.split_whitespace() {
+        if value == "|" {
+            input = false;
+        } else if input {
+            patterns.push(value);
+        } else {
+            outputs.push(value);
+        }
+    }
+    (patterns, outputs)
+}
+
 // The output is wrapped in a Result to allow matching on errors
 // Returns an Iterator to the Reader of the lines of the file.
 fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
@@ -0,0 +1,139 @@
+use std::env;
+use std::io::{self, Write};
+
+// Small xorshift generator so the output is reproducible from a seed
+struct Random {
+    state: u64
+}
+
+impl Random {
+    fn new(seed: u64) -> Random {
+        Random {
+            // A zero state would only ever produce zeros
+            state: if seed == 0 { 0x9e3779b97f4a7c15 } else { seed }
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    fn below(&mut self, max: usize) -> usize {
+        (self.next() % max as u64) as usize
+    }
+
+    fn shuffle<T>(&mut self, values: &mut [T]) {
+        for i in (1..values.len()).rev() {
+            let j = self.below(i + 1);
+            values.swap(i, j);
+        }
+    }
+}
+
+struct Generator {
+    digits: Vec<&'static str>,
+    random: Random
+}
+
+impl Generator {
+    fn new(seed: u64) -> Generator {
+        Generator {
+            digits: vec!["abcefg", "cf", "acdeg", "acdfg", "bcdf", "abdfg", "abdefg", "acf", "abcdefg", "abcdfg"],
+            random: Random::new(seed)
+        }
+    }
+
+    // Rewires the segments of a digit and shuffles the order of the letters
+    fn scramble(&mut self, wiring: &[char], digit: usize) -> String {
+        let mut result: Vec<char> = self.digits[digit].chars().map(|c| wiring[c as usize - 'a' as usize]).collect();
+        self.random.shuffle(&mut result);
+        result.into_iter().collect()
+    }
+
+    fn line(&mut self, number: usize) -> String {
+        let mut wiring: Vec<char> = "abcdefg".chars().collect();
+        self.random.shuffle(&mut wiring);
+
+        let mut order: Vec<usize> = (0..10).collect();
+        self.random.shuffle(&mut order);
+        let mut entries: Vec<String> = Vec::new();
+        for digit in order {
+            entries.push(self.scramble(&wiring, digit));
+        }
+        entries.push(String::from("|"));
+        let output = format!("{:04}", number);
+        for c in output.chars() {
+            entries.push(self.scramble(&wiring, c.to_digit(10).unwrap() as usize));
+        }
+        entries.join(" ")
+    }
+
+    fn number(&mut self) -> usize {
+        self.random.below(10000)
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() <= 1 {
+        println!("No parameters specified");
+        println!("Usage: {} <seed> [-c count] [number ...]", &args[0]);
+        return;
+    }
+
+    let seed: u64 = match args[1].parse() {
+        Ok(s) => s,
+        Err(_) => {
+            println!("Invalid seed {:?}", &args[1]);
+            return;
+        }
+    };
+    let mut count: usize = 200;
+    let mut numbers: Vec<usize> = Vec::new();
+    let mut i = 2;
+    while i < args.len() {
+        if args[i] == "-c" {
+            i += 1;
+            match args.get(i).map(|v| v.parse::<usize>()) {
+                Some(Ok(n)) => count = n,
+                _ => {
+                    println!("Invalid count {:?}", args.get(i));
+                    return;
+                }
+            }
+        } else {
+            match args[i].parse::<usize>() {
+                Ok(n) if n < 10000 => numbers.push(n),
+                _ => {
+                    println!("Invalid number {:?}", &args[i]);
+                    return;
+                }
+            }
+        }
+        i += 1;
+    }
+
+    let mut generator = Generator::new(seed);
+    if numbers.is_empty() {
+        for _i in 0..count {
+            let n = generator.number();
+            numbers.push(n);
+        }
+    }
+
+    // The known answer goes to stderr so stdout can be redirected to a fixture
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut total = 0;
+    for n in numbers {
+        total += n;
+        if writeln!(out, "{}", generator.line(n)).is_err() {
+            return;
+        }
+    }
+    eprintln!("Total: {}", total);
+}
@@ -3,46 +3,84 @@ use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
 
+// Two bytes per cell is enough for elevations in metres
+type Height = i16;
+
 struct Floor {
-    floor: String,
+    floor: Vec<Height>,
     width: usize,
-    depth: usize
+    depth: usize,
+    // Whether rows are separated integers rather than runs of digits
+    integers: bool
 
 }
 
 impl Floor {
     fn new() -> Floor {
         Floor{
-            floor: String::from(""),
+            floor: Vec::new(),
             width: 0,
-            depth: 0
+            depth: 0,
+            integers: false
+        }
+    }
+
+    // Integer grids are told apart from runs of digits by a separator or minus sign
+    // anywhere in the file, or by rows of different lengths, which a grid of digits
+    // can't have. Deciding once means a single column such as 120 or -5 reads the
+    // same as the other rows
+    fn separated(rows: &[io::Result<String>]) -> bool {
+        let rows: Vec<&str> = rows.iter().flatten().map(|row| row.trim()).filter(|row| !row.is_empty()).collect();
+        rows.iter().any(|row| row.contains(|c: char| c == ',' || c == '-' || c.is_whitespace()))
+            || rows.iter().any(|row| row.len() != rows[0].len())
+    }
+
+    // Rows are either runs of single digits or whitespace/comma separated integers
+    fn parse_row(str: &str, integers: bool) -> Result<Vec<Height>, String> {
+        const RADIX: u32 = 10;
+        if integers {
+            str.split(|c: char| c == ',' || c.is_whitespace())
+               .filter(|t| !t.is_empty())
+               .map(|t| t.parse::<Height>().map_err(|e| format!("invalid height {:?}: {}", t, e)))
+               .collect()
+        } else {
+            str.trim().chars()
+               .map(|c| c.to_digit(RADIX).map(|d| d as Height).ok_or(format!("invalid digit {:?}", c)))
+               .collect()
         }
     }
 
-    fn add(&mut self, str: &str) {
+    fn add(&mut self, str: &str) -> Result<(), String> {
+        if str.trim() == "" {
+            return Ok(());
+        }
+        let row = Floor::parse_row(str, self.integers)?;
         if self.width == 0 {
-            self.width = str.len();
+            self.width = row.len();
+        } else if row.len() != self.width {
+            return Err(format!("expected {} heights but found {}", self.width, row.len()));
         }
         self.depth += 1;
-        self.floor.push_str(str);
+        self.floor.extend(row);
+        Ok(())
     }
 
-    fn lowest(&self, x: usize, y: usize) -> Option<usize> {
+    fn lowest(&self, x: usize, y: usize) -> Option<Height> {
         let pos = y * self.width + x;
-        let ch = &self.floor[pos..pos+1];
-        if x > 0 && &self.floor[pos - 1..pos] <= ch {
+        let h = self.floor[pos];
+        if x > 0 && self.floor[pos - 1] <= h {
             return None;
         }
-        if x < self.width - 1 && &self.floor[pos + 1..pos + 2] <= ch {
+        if x < self.width - 1 && self.floor[pos + 1] <= h {
             return None;
         }
-        if y > 0 && &self.floor[pos - self.width..pos - self.width + 1] <= ch {
+        if y > 0 && self.floor[pos - self.width] <= h {
             return None;
         }
-        if y < self.depth - 1 && &self.floor[pos + self.width..pos + self.width + 1] <= ch {
+        if y < self.depth - 1 && self.floor[pos + self.width] <= h {
             return None;
         }
-        return Some(ch.parse().unwrap());
+        return Some(h);
     }
 }
 
@@ -57,16 +95,21 @@ fn main() {
     println!("Reading {:?}", &args[1]);
     if let Ok(lines) = read_lines(&args[1]) {
         let mut floor = Floor::new();
-        for line in lines {
+        let lines: Vec<io::Result<String>> = lines.collect();
+        floor.integers = Floor::separated(&lines);
+        for (n, line) in lines.into_iter().enumerate() {
             if let Ok(strval) = line {
-                floor.add(&strval);
+                if let Err(e) = floor.add(&strval) {
+                    println!("Line {}: {}", n + 1, e);
+                    return;
+                }
             }
         }
-        let mut risk = 0;
+        let mut risk: i64 = 0;
         for x in 0..floor.width {
             for y in 0..floor.depth {
                 if let Some(r) = floor.lowest(x, y) {
-                    risk += r + 1;
+                    risk += r as i64 + 1;
                 }
             }
         }
@@ -3,71 +3,312 @@ extern crate termion;
 use termion::{color, style};
 
 use std::env;
+use std::cmp;
 use std::fmt;
 use std::fs::File;
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, Write};
 use std::path::Path;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+
+// Disjoint sets over grid positions, used to merge cells into basins
+struct UnionFind {
+    parent: Vec<usize>,
+    size: Vec<usize>
+}
+
+impl UnionFind {
+    fn new(area: usize) -> UnionFind {
+        UnionFind {
+            parent: (0..area).collect(),
+            size: vec![1; area]
+        }
+    }
+
+    fn find(&mut self, mut pos: usize) -> usize {
+        while self.parent[pos] != pos {
+            // Path halving keeps the trees shallow
+            self.parent[pos] = self.parent[self.parent[pos]];
+            pos = self.parent[pos];
+        }
+        pos
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let mut ra = self.find(a);
+        let mut rb = self.find(b);
+        if ra == rb {
+            return;
+        }
+        if self.size[ra] < self.size[rb] {
+            std::mem::swap(&mut ra, &mut rb);
+        }
+        self.parent[rb] = ra;
+        self.size[ra] += self.size[rb];
+    }
+}
+
+struct Basin {
+    id: usize,
+    size: usize,
+    low: usize,
+    left: usize,
+    top: usize,
+    right: usize,
+    bottom: usize
+}
+
+struct Basins {
+    label: Vec<Option<usize>>,
+    basins: Vec<Basin>
+}
+
+impl Basins {
+    // Returns the k largest basins, biggest first
+    fn top(&self, k: usize) -> Vec<&Basin> {
+        let mut sorted: Vec<&Basin> = self.basins.iter().collect();
+        sorted.sort_by(|a, b| b.size.cmp(&a.size).then(a.id.cmp(&b.id)));
+        sorted.truncate(k);
+        sorted
+    }
+}
+
+// Which cells count as adjacent, as (dx, dy) offsets from a cell
+enum Neighbourhood {
+    Offsets(Vec<(isize, isize)>),
+    // Hexagonal cells with every odd row shifted half a cell to the right
+    Hex
+}
+
+impl Neighbourhood {
+    fn orthogonal() -> Neighbourhood {
+        Neighbourhood::Offsets(vec![(0, -1), (0, 1), (-1, 0), (1, 0)])
+    }
+
+    fn diagonal() -> Neighbourhood {
+        Neighbourhood::Offsets(vec![(0, -1), (0, 1), (-1, 0), (1, 0), (-1, -1), (1, -1), (-1, 1), (1, 1)])
+    }
+
+    // Accepts 4, 8, hex or the name of a file with one "dx,dy" offset per line
+    fn parse(spec: &str) -> Result<Neighbourhood, String> {
+        match spec {
+            "4" => Ok(Neighbourhood::orthogonal()),
+            "8" => Ok(Neighbourhood::diagonal()),
+            "hex" => Ok(Neighbourhood::Hex),
+            _ => {
+                let lines = read_lines(spec).map_err(|e| format!("cannot read mask {:?}: {}", spec, e))?;
+                let mut offsets: Vec<(isize, isize)> = Vec::new();
+                for line in lines {
+                    let line = line.map_err(|e| e.to_string())?;
+                    let line = line.trim();
+                    if line == "" || line.starts_with('#') {
+                        continue;
+                    }
+                    let d: Vec<isize> = line.split(|c: char| c == ',' || c.is_whitespace())
+                                            .filter(|t| !t.is_empty())
+                                            .map(|t| t.parse::<isize>().map_err(|e| format!("invalid offset {:?}: {}", line, e)))
+                                            .collect::<Result<Vec<isize>, String>>()?;
+                    if d.len() != 2 || d == [0, 0] {
+                        return Err(format!("invalid offset {:?}", line));
+                    }
+                    offsets.push((d[0], d[1]));
+                }
+                Ok(Neighbourhood::Offsets(offsets))
+            }
+        }
+    }
+
+    fn offsets(&self, y: usize) -> Vec<(isize, isize)> {
+        match self {
+            Neighbourhood::Offsets(offsets) => offsets.clone(),
+            Neighbourhood::Hex if y.is_multiple_of(2) => vec![(0, -1), (-1, -1), (-1, 0), (1, 0), (0, 1), (-1, 1)],
+            Neighbourhood::Hex => vec![(0, -1), (1, -1), (-1, 0), (1, 0), (0, 1), (1, 1)]
+        }
+    }
+}
+
+// Two bytes per cell is enough for elevations in metres
+type Height = i16;
 
 struct Floor {
-    temp: String,
-    floor: Vec<usize>,
+    floor: Vec<Height>,
     width: usize,
     depth: usize,
+    // Whether rows are separated integers rather than runs of digits
+    integers: bool,
     area: usize,
-    combined: BTreeSet<usize>
+    wall: Height,
+    neighbourhood: Neighbourhood,
+    combined: BTreeSet<usize>,
+    water: Vec<Height>
 
 }
 
 impl Floor {
     fn new() -> Floor {
         Floor{
-            temp: String::from(""),
             floor: Vec::new(),
             width: 0,
             depth: 0,
+            integers: false,
             area: 0,
-            combined: BTreeSet::new()
+            wall: 9,
+            neighbourhood: Neighbourhood::orthogonal(),
+            combined: BTreeSet::new(),
+            water: Vec::new()
         }
     }
 
-    fn add(&mut self, str: &str) {
+    // Integer grids are told apart from runs of digits by a separator or minus sign
+    // anywhere in the file, or by rows of different lengths, which a grid of digits
+    // can't have. Deciding once means a single column such as 120 or -5 reads the
+    // same as the other rows
+    fn separated(rows: &[io::Result<String>]) -> bool {
+        let rows: Vec<&str> = rows.iter().flatten().map(|row| row.trim()).filter(|row| !row.is_empty()).collect();
+        rows.iter().any(|row| row.contains(|c: char| c == ',' || c == '-' || c.is_whitespace()))
+            || rows.iter().any(|row| row.len() != rows[0].len())
+    }
+
+    // Rows are either runs of single digits or whitespace/comma separated integers
+    fn parse_row(str: &str, integers: bool) -> Result<Vec<Height>, String> {
+        const RADIX: u32 = 10;
+        if integers {
+            str.split(|c: char| c == ',' || c.is_whitespace())
+               .filter(|t| !t.is_empty())
+               .map(|t| t.parse::<Height>().map_err(|e| format!("invalid height {:?}: {}", t, e)))
+               .collect()
+        } else {
+            str.trim().chars()
+               .map(|c| c.to_digit(RADIX).map(|d| d as Height).ok_or(format!("invalid digit {:?}", c)))
+               .collect()
+        }
+    }
+
+    fn add(&mut self, str: &str) -> Result<(), String> {
+        if str.trim() == "" {
+            return Ok(());
+        }
+        let row = Floor::parse_row(str, self.integers)?;
         if self.width == 0 {
-            self.width = str.len();
+            self.width = row.len();
+        } else if row.len() != self.width {
+            return Err(format!("expected {} heights but found {}", self.width, row.len()));
         }
         self.depth += 1;
-        self.temp.push_str(str);
+        self.floor.extend(row);
+        Ok(())
     }
 
     fn convert(&mut self) {
-        const RADIX: u32 = 10;
-        if self.temp != "" {
-            self.area = self.temp.len();
-            self.floor = self.temp.chars().map(|c| c.to_digit(RADIX).unwrap() as usize).collect();
-            self.temp.clear();
-        }
+        self.area = self.floor.len();
+    }
+
+    // Width of the widest height, so multi-digit grids line up when displayed
+    fn cell_width(&self) -> usize {
+        self.floor.iter().map(|h| h.to_string().len()).max().unwrap_or(1)
     }
 
     // Returns the vector of adjacent points
     fn adjacent(&self, pos: usize) -> Vec<usize> {
         let mut result: Vec<usize> = Vec::new();
-        if pos >= self.width {
-            result.push(pos - self.width);
+        let (x, y) = ((pos % self.width) as isize, (pos / self.width) as isize);
+        for (dx, dy) in self.neighbourhood.offsets(y as usize) {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx >= 0 && ny >= 0 && (nx as usize) < self.width && (ny as usize) < self.depth {
+                result.push(ny as usize * self.width + nx as usize);
+            }
+        }
+        result
+    }
+
+    fn wall(&self, pos: usize) -> bool {
+        self.floor[pos] >= self.wall
+    }
+
+    // Labels every non-wall cell with the id of its basin in a single sweep
+    fn label(&self) -> Basins {
+        let mut sets = UnionFind::new(self.area);
+        for pos in 0..self.area {
+            if self.wall(pos) {
+                continue;
+            }
+            for neighbour in self.adjacent(pos) {
+                if !self.wall(neighbour) {
+                    sets.union(pos, neighbour);
+                }
+            }
         }
-        if pos < self.area - self.width {
-            result.push(pos + self.width);
+
+        let mut ids: Vec<Option<usize>> = vec![None; self.area];
+        let mut label: Vec<Option<usize>> = vec![None; self.area];
+        let mut basins: Vec<Basin> = Vec::new();
+        for (pos, slot) in label.iter_mut().enumerate() {
+            if self.wall(pos) {
+                continue;
+            }
+            let root = sets.find(pos);
+            let (x, y) = (pos % self.width, pos / self.width);
+            let id = match ids[root] {
+                Some(id) => id,
+                None => {
+                    let id = basins.len();
+                    ids[root] = Some(id);
+                    basins.push(Basin { id, size: 0, low: pos, left: x, top: y, right: x, bottom: y });
+                    id
+                }
+            };
+            let basin = &mut basins[id];
+            basin.size += 1;
+            if self.floor[pos] < self.floor[basin.low] {
+                basin.low = pos;
+            }
+            basin.left = cmp::min(basin.left, x);
+            basin.right = cmp::max(basin.right, x);
+            basin.bottom = cmp::max(basin.bottom, y);
+            *slot = Some(id);
         }
-        let x: usize = pos % self.width;
-        if x > 0 {
-            result.push(pos - 1);
+        Basins {
+            label,
+            basins
         }
-        if x < self.width - 1 {
-            result.push(pos + 1);
+    }
+
+    // Floods the floor from the edges inwards, leaving the water surface level
+    // of every cell in self.water, and returns the total volume held
+    fn rain(&mut self) -> usize {
+        let mut level: Vec<Option<Height>> = vec![None; self.area];
+        let mut queue: BinaryHeap<cmp::Reverse<(Height, usize)>> = BinaryHeap::new();
+        for (pos, slot) in level.iter_mut().enumerate() {
+            let (x, y) = (pos % self.width, pos / self.width);
+            if x == 0 || y == 0 || x == self.width - 1 || y == self.depth - 1 {
+                *slot = Some(self.floor[pos]);
+                queue.push(cmp::Reverse((self.floor[pos], pos)));
+            }
         }
-        result
+        // Water spreads to each cell from the lowest spill point seen so far
+        while let Some(cmp::Reverse((surface, pos))) = queue.pop() {
+            for neighbour in self.adjacent(pos) {
+                if level[neighbour].is_none() {
+                    let l = cmp::max(surface, self.floor[neighbour]);
+                    level[neighbour] = Some(l);
+                    queue.push(cmp::Reverse((l, neighbour)));
+                }
+            }
+        }
+        // Cells the mask never lets the flood reach hold no water
+        self.water = level.into_iter().zip(&self.floor).map(|(l, h)| l.unwrap_or(*h)).collect();
+        (0..self.area).map(|pos| self.water_depth(pos)).sum()
+    }
+
+    // Depth of water standing on a cell after rain
+    fn water_depth(&self, pos: usize) -> usize {
+        (self.water[pos] as i32 - self.floor[pos] as i32) as usize
+    }
+
+    fn flooded(&self, pos: usize) -> bool {
+        !self.water.is_empty() && self.water[pos] > self.floor[pos]
     }
 
-    fn lowest(&self, pos: usize) -> Option<usize> {
+    fn lowest(&self, pos: usize) -> Option<Height> {
         let d = self.floor[pos];
         for neighbour in self.adjacent(pos) {
             if self.floor[neighbour] <= d {
@@ -109,21 +350,28 @@ impl Floor {
     }
 
     fn basin(&mut self, pos: usize) -> usize {
+        // A low point at or above the wall height is itself a wall and holds no basin
+        if self.wall(pos) {
+            return 0;
+        }
         let mut included: BTreeSet<usize> = BTreeSet::new();
-        let mut pending: Vec<Vec<usize>> = vec![Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new()];
+        let mut pending: BTreeMap<Height, Vec<usize>> = BTreeMap::new();
 
-        pending[self.floor[pos]] = vec![pos];
-        for i in self.floor[pos]..9 {
-            let level = &pending[i].clone();
+        pending.insert(self.floor[pos], vec![pos]);
+        let mut current = self.floor[pos];
+        // Levels are grown in height order up to, but not including, the walls
+        while let Some((&i, level)) = pending.range(current..self.wall).next() {
+            let level = &level.clone();
             for point in level {
                 if let Some(neighbours) = self.flow(*point, &included) {
                     included.insert(*point);
                     self.combined.insert(*point);
                     for neighbour in neighbours {
-                        pending[self.floor[neighbour]].push(neighbour);
+                        pending.entry(self.floor[neighbour]).or_default().push(neighbour);
                     }
                 }
             }
+            current = i + 1;
         }
 
         let area: usize = included.len();
@@ -131,18 +379,192 @@ impl Floor {
     }
 }
 
+// Where the water on each cell ends up when it runs downhill
+struct DrainageMap {
+    // Steepest downhill neighbour, None for sinks and walls
+    down: Vec<Option<usize>>,
+    // Low point reached by always taking the steepest descent
+    sink: Vec<Option<usize>>,
+    // Number of steps to reach the sink
+    length: Vec<usize>,
+    // Cells whose steepest descent passes through this one, including itself
+    upstream: Vec<usize>,
+    // Every low point reachable by some downhill route
+    sinks: Vec<BTreeSet<usize>>
+}
+
+impl DrainageMap {
+    fn new(floor: &Floor) -> DrainageMap {
+        let mut order: Vec<usize> = (0..floor.area).filter(|pos| !floor.wall(*pos)).collect();
+        order.sort_by_key(|pos| floor.floor[*pos]);
+
+        let mut down: Vec<Option<usize>> = vec![None; floor.area];
+        let mut sink: Vec<Option<usize>> = vec![None; floor.area];
+        let mut length: Vec<usize> = vec![0; floor.area];
+        let mut upstream: Vec<usize> = vec![0; floor.area];
+        let mut sinks: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); floor.area];
+
+        // Lower cells are finished before anything that can drain into them
+        for pos in &order {
+            let pos = *pos;
+            let lower: Vec<usize> = floor.adjacent(pos).into_iter()
+                .filter(|n| !floor.wall(*n) && floor.floor[*n] < floor.floor[pos])
+                .collect();
+            down[pos] = lower.iter().cloned().min_by_key(|n| (floor.floor[*n], *n));
+            match down[pos] {
+                Some(next) => {
+                    sink[pos] = sink[next];
+                    length[pos] = length[next] + 1;
+                },
+                None => {
+                    sink[pos] = Some(pos);
+                }
+            }
+            if lower.is_empty() {
+                sinks[pos].insert(pos);
+            }
+            for n in lower {
+                let reachable = sinks[n].clone();
+                sinks[pos].extend(reachable);
+            }
+            upstream[pos] = 1;
+        }
+        for pos in order.iter().rev() {
+            if let Some(next) = down[*pos] {
+                upstream[next] += upstream[*pos];
+            }
+        }
+
+        DrainageMap {
+            down,
+            sink,
+            length,
+            upstream,
+            sinks
+        }
+    }
+
+    // Cells that can drain into more than one low point
+    fn divides(&self) -> Vec<usize> {
+        (0..self.sinks.len()).filter(|pos| self.sinks[*pos].len() > 1).collect()
+    }
+
+    fn report(&self, floor: &Floor) {
+        let xy = |pos: usize| (pos % floor.width, pos / floor.width);
+        let lows: Vec<usize> = (0..floor.area).filter(|pos| self.sink[*pos] == Some(*pos)).collect();
+        println!("Sinks: {}", lows.len());
+        for low in &lows {
+            let cells = self.sink.iter().filter(|s| **s == Some(*low)).count();
+            let longest = (0..floor.area).filter(|pos| self.sink[*pos] == Some(*low)).map(|pos| self.length[pos]).max().unwrap_or(0);
+            println!("Sink {:?} = {}: drains {} cells, longest flow path {}", xy(*low), floor.floor[*low], cells, longest);
+        }
+        if let Some(pos) = (0..floor.area).max_by_key(|pos| (self.length[*pos], cmp::Reverse(*pos))) {
+            let mut path: Vec<(usize, usize)> = vec![xy(pos)];
+            let mut next = self.down[pos];
+            while let Some(n) = next {
+                path.push(xy(n));
+                next = self.down[n];
+            }
+            println!("Longest flow path from {:?}: {:?}", xy(pos), path);
+        }
+        let divides = self.divides();
+        println!("Cells draining to more than one low point: {}", divides.len());
+        for pos in divides {
+            let targets: Vec<(usize, usize)> = self.sinks[pos].iter().map(|s| xy(*s)).collect();
+            println!("  {:?} = {}, upstream area {}, steepest to {:?}, reaches {:?}",
+                     xy(pos), floor.floor[pos], self.upstream[pos], self.sink[pos].map(xy), targets);
+        }
+    }
+}
+
+// Writes the floor as binary netpbm images, each cell drawn as a scale x scale block
+struct Image {
+    scale: usize
+}
+
+impl Image {
+    fn new(scale: usize) -> Image {
+        Image {
+            scale
+        }
+    }
+
+    fn write(&self, path: &str, magic: &str, floor: &Floor, pixel: impl Fn(usize) -> Vec<u8>) -> io::Result<()> {
+        let mut file = io::BufWriter::new(File::create(path)?);
+        write!(file, "{}\n{} {}\n255\n", magic, floor.width * self.scale, floor.depth * self.scale)?;
+        for y in 0..floor.depth {
+            let mut row: Vec<u8> = Vec::new();
+            for x in 0..floor.width {
+                let p = pixel(y * floor.width + x);
+                for _i in 0..self.scale {
+                    row.extend(&p);
+                }
+            }
+            for _i in 0..self.scale {
+                file.write_all(&row)?;
+            }
+        }
+        file.flush()
+    }
+
+    // Greyscale heightmap, stretched so the lowest cell is black and the highest white
+    fn write_pgm(&self, path: &str, floor: &Floor) -> io::Result<()> {
+        let min = *floor.floor.iter().min().unwrap_or(&0);
+        let max = *floor.floor.iter().max().unwrap_or(&0);
+        let range = cmp::max(max as i64 - min as i64, 1);
+        self.write(path, "P5", floor, |pos| vec![((floor.floor[pos] as i64 - min as i64) * 255 / range) as u8])
+    }
+
+    // Each basin in its own colour, walls in black and low points in white
+    fn write_ppm(&self, path: &str, floor: &Floor, basins: &Basins) -> io::Result<()> {
+        self.write(path, "P6", floor, |pos| {
+            match basins.label[pos] {
+                None => vec![0, 0, 0],
+                // A region between walls can hold several local minima, so mark them all
+                Some(_) if floor.lowest(pos).is_some() => vec![255, 255, 255],
+                Some(id) => Image::colour(id)
+            }
+        })
+    }
+
+    // Spreads the hues by the golden angle so neighbouring ids look different
+    fn colour(id: usize) -> Vec<u8> {
+        let hue = (id as f64 * 137.507764) % 360.0;
+        let (s, v) = (0.65, 0.9);
+        let c = v * s;
+        let x = c * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+        let m = v - c;
+        let (r, g, b) = match (hue / 60.0) as usize {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x)
+        };
+        vec![((r + m) * 255.0) as u8, ((g + m) * 255.0) as u8, ((b + m) * 255.0) as u8]
+    }
+}
+
 impl fmt::Display for Floor {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let w = self.cell_width();
         for i in (0..self.area).step_by(self.width as usize) {
             for j in i..i+self.width {
-                if self.combined.contains(&j) {
-                    fmt.write_fmt(format_args!("{}{}{}", style::Bold, self.floor[j], style::Reset))?;
-                } else if self.floor[j] == 9 {
-                    fmt.write_fmt(format_args!("{}{}{}", color::Fg(color::Blue), self.floor[j], color::Fg(color::Reset)))?;
+                if w > 1 && j > i {
+                    fmt.write_str(" ")?;
+                }
+                let h = format!("{:>w$}", self.floor[j], w = w);
+                if self.flooded(j) {
+                    fmt.write_fmt(format_args!("{}{}{}", color::Bg(color::Blue), h, color::Bg(color::Reset)))?;
+                } else if self.combined.contains(&j) {
+                    fmt.write_fmt(format_args!("{}{}{}", style::Bold, h, style::Reset))?;
+                } else if self.wall(j) {
+                    fmt.write_fmt(format_args!("{}{}{}", color::Fg(color::Blue), h, color::Fg(color::Reset)))?;
                 } else if self.highest(j) {
-                    fmt.write_fmt(format_args!("{}{}{}", color::Fg(color::Green), self.floor[j], color::Fg(color::Reset)))?;
+                    fmt.write_fmt(format_args!("{}{}{}", color::Fg(color::Green), h, color::Fg(color::Reset)))?;
                 } else {
-                    fmt.write_fmt(format_args!("{}{}{}", color::Fg(color::Yellow), self.floor[j], color::Fg(color::Reset)))?;
+                    fmt.write_fmt(format_args!("{}{}{}", color::Fg(color::Yellow), h, color::Fg(color::Reset)))?;
                 }
             }
             fmt.write_str("\n")?;
@@ -160,35 +582,173 @@ fn main() {
         return;
     }
 
-    println!("Reading {:?}", &args[1]);
-    if let Ok(lines) = read_lines(&args[1]) {
+    let mut filename: Option<&String> = None;
+    let mut top: usize = 3;
+    let mut flood: bool = false;
+    let mut show: bool = false;
+    let mut pgm: Option<&String> = None;
+    let mut ppm: Option<&String> = None;
+    let mut scale: usize = 1;
+    let mut drainage: bool = false;
+    let mut rain: bool = false;
+    let mut wall: Height = 9;
+    let mut neighbourhood = Neighbourhood::orthogonal();
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--top" => {
+                i += 1;
+                match args.get(i).map(|v| v.parse::<usize>()) {
+                    Some(Ok(k)) => top = k,
+                    _ => {
+                        println!("Invalid value for --top");
+                        return;
+                    }
+                }
+            },
+            "--flood" => flood = true,
+            "--show" => show = true,
+            "--drainage" => drainage = true,
+            "--rain" => rain = true,
+            "--neighbours" => {
+                i += 1;
+                match args.get(i).map(|v| Neighbourhood::parse(v)) {
+                    Some(Ok(n)) => neighbourhood = n,
+                    Some(Err(e)) => {
+                        println!("{}", e);
+                        return;
+                    },
+                    None => {
+                        println!("Missing value for --neighbours");
+                        return;
+                    }
+                }
+            },
+            "--wall" => {
+                i += 1;
+                match args.get(i).map(|v| v.parse::<Height>()) {
+                    Some(Ok(w)) => wall = w,
+                    _ => {
+                        println!("Invalid value for --wall");
+                        return;
+                    }
+                }
+            },
+            "--pgm" => {
+                i += 1;
+                pgm = args.get(i);
+            },
+            "--ppm" => {
+                i += 1;
+                ppm = args.get(i);
+            },
+            "--scale" => {
+                i += 1;
+                match args.get(i).map(|v| v.parse::<usize>()) {
+                    Some(Ok(k)) if k > 0 => scale = k,
+                    _ => {
+                        println!("Invalid value for --scale");
+                        return;
+                    }
+                }
+            },
+            _ => filename = Some(&args[i])
+        }
+        i += 1;
+    }
+    let filename = match filename {
+        Some(f) => f,
+        None => {
+            println!("No input file specified");
+            return;
+        }
+    };
+
+    println!("Reading {:?}", filename);
+    if let Ok(lines) = read_lines(filename) {
         let mut floor = Floor::new();
-        for line in lines {
+        floor.wall = wall;
+        floor.neighbourhood = neighbourhood;
+        let lines: Vec<io::Result<String>> = lines.collect();
+        floor.integers = Floor::separated(&lines);
+        for (n, line) in lines.into_iter().enumerate() {
             if let Ok(strval) = line {
-                floor.add(&strval);
+                if let Err(e) = floor.add(&strval) {
+                    println!("Line {}: {}", n + 1, e);
+                    return;
+                }
             }
         }
         floor.convert();
-        let mut areas: Vec<usize> = vec![0, 0, 0];
-        for pos in 0..floor.area {
-            if floor.lowest(pos).is_some() {
-                let area = floor.basin(pos);
-                // println!("{}", floor);
-
-                if area > areas[0] {
-                    areas[2] = areas[1];
-                    areas[1] = areas[0];
-                    areas[0] = area;
-                } else if area > areas[1] {
-                    areas[2] = areas[1];
-                    areas[1] = area;
-                } else if area > areas[2] {
-                    areas[2] = area;
-                }
-                // println!("Area: {}, areas: {:?}", area, areas);
-            }
-        }
-        println!("Product = {}", areas[0] * areas[1] * areas[2]);
+        let risk: i64 = (0..floor.area).filter_map(|pos| floor.lowest(pos)).map(|h| h as i64 + 1).sum();
+        println!("Risk: {}", risk);
+        let mut areas: Vec<usize> = Vec::new();
+        if flood {
+            // The original flood fill from each low point, kept for cross-checking
+            for pos in 0..floor.area {
+                if floor.lowest(pos).is_some() && !floor.wall(pos) {
+                    areas.push(floor.basin(pos));
+                }
+            }
+            areas.sort_by(|a, b| b.cmp(a));
+            areas.truncate(top);
+        } else {
+            let basins = floor.label();
+            println!("Basins: {}", basins.basins.len());
+            for basin in basins.top(top) {
+                println!("Basin {}: size {}, low point ({}, {}) = {}, bounds ({}, {})-({}, {})",
+                         basin.id, basin.size,
+                         basin.low % floor.width, basin.low / floor.width, floor.floor[basin.low],
+                         basin.left, basin.top, basin.right, basin.bottom);
+                areas.push(basin.size);
+                for pos in 0..floor.area {
+                    if basins.label[pos] == Some(basin.id) {
+                        floor.combined.insert(pos);
+                    }
+                }
+            }
+        }
+        if rain {
+            let volume = floor.rain();
+            let basins = floor.label();
+            let mut volumes: Vec<usize> = vec![0; basins.basins.len()];
+            let mut ridges = 0;
+            for pos in 0..floor.area {
+                let depth = floor.water_depth(pos);
+                match basins.label[pos] {
+                    Some(id) => volumes[id] += depth,
+                    None => ridges += depth
+                }
+            }
+            for basin in &basins.basins {
+                if volumes[basin.id] > 0 {
+                    let surface = (0..floor.area).filter(|pos| basins.label[*pos] == Some(basin.id) && floor.flooded(*pos)).map(|pos| floor.water[pos]).max().unwrap();
+                    println!("Basin {}: holds {}, surface level {}", basin.id, volumes[basin.id], surface);
+                }
+            }
+            if ridges > 0 {
+                println!("Ridges: holds {}", ridges);
+            }
+            println!("Water held: {}", volume);
+        }
+        if show {
+            println!("{}", floor);
+        }
+        if drainage {
+            DrainageMap::new(&floor).report(&floor);
+        }
+        let image = Image::new(scale);
+        if let Some(path) = pgm {
+            if let Err(e) = image.write_pgm(path, &floor) {
+                println!("Failed to write {:?}: {}", path, e);
+            }
+        }
+        if let Some(path) = ppm {
+            if let Err(e) = image.write_ppm(path, &floor, &floor.label()) {
+                println!("Failed to write {:?}: {}", path, e);
+            }
+        }
+        println!("Product = {}", areas.iter().product::<usize>());
     }
 }
 